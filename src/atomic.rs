@@ -0,0 +1,57 @@
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use crate::error::{Error, Result};
+
+/// Write a file through a temporary sibling that is renamed over `output` once complete.
+///
+/// `write` receives the temporary path. If it fails, the temporary file is removed and
+/// `output` is left as it was, so an interrupted or failed encode never leaves a truncated
+/// file behind and never clobbers an existing one. The temporary file lives in the same
+/// directory so the final rename cannot cross file systems.
+pub fn write_atomically(output: &Path, write: impl FnOnce(&Path) -> Result<()>) -> Result<()> {
+    let temp = temp_path(output);
+    in_flight().push(temp.clone());
+    let result = write(&temp).and_then(|()| commit(&temp, output));
+    if result.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    in_flight().retain(|path| *path != temp);
+    result
+}
+
+/// Delete the temporary files of writes still in progress.
+///
+/// For a process about to exit without letting them finish, such as on a second interrupt.
+pub fn remove_temp_files() {
+    for temp in in_flight().drain(..) {
+        let _ = fs::remove_file(temp);
+    }
+}
+
+/// Temporary files of the writes in progress
+fn in_flight() -> std::sync::MutexGuard<'static, Vec<PathBuf>> {
+    static IN_FLIGHT: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+    IN_FLIGHT.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Flush the finished temporary file to disk and move it into place
+fn commit(temp: &Path, output: &Path) -> Result<()> {
+    let output_error = |e| Error::output(output, e);
+    File::open(temp).and_then(|f| f.sync_all()).map_err(output_error)?;
+
+    // Replacing a file keeps its permissions, as an in-place edit would
+    if let Ok(metadata) = fs::metadata(output) {
+        fs::set_permissions(temp, metadata.permissions()).map_err(output_error)?;
+    }
+    fs::rename(temp, output).map_err(output_error)
+}
+
+/// `dir/.name.PID-N.tmp`, unique per process and call
+fn temp_path(output: &Path) -> PathBuf {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    let name = output.file_name().unwrap_or_default().to_string_lossy();
+    let n = COUNTER.fetch_add(1, Ordering::Relaxed);
+    output.with_file_name(format!(".{}.{}-{}.tmp", name, std::process::id(), n))
+}
@@ -1,14 +1,37 @@
-use anyhow::{anyhow, Result};
+use crate::atomic::write_atomically;
+use crate::error::{Error, Result};
 use ebur128::EbuR128;
 use hound::{SampleFormat, WavReader, WavSpec, WavWriter};
+use rubato::{FftFixedIn, Resampler};
 use std::fs::File;
-use std::io::BufWriter;
+use std::io::{self, BufWriter, Write};
 use std::path::Path;
 
-#[allow(dead_code)]
+/// Full-scale magnitude of signed integer PCM at the given bit depth (2^(bits-1)).
+///
+/// Both the decoders and the encoders scale by this value so that an integer
+/// sample of up to 24 bits survives a round trip through f32 unchanged. f32 holds
+/// only 24 significant bits, so 32-bit samples lose their lowest bits on the way;
+/// at 32 bits only the verbatim copy of `copy_wav_pcm` is bit-exact.
+pub fn int_full_scale(bits: u16) -> f64 {
+    (1i64 << (bits - 1)) as f64
+}
+
+/// Convert a signed integer PCM sample to f32 in [-1.0, 1.0), exactly for up to 24 bits.
+pub fn int_to_f32(v: i32, bits: u16) -> f32 {
+    (v as f64 / int_full_scale(bits)) as f32
+}
+
+/// Convert an f32 sample to signed integer PCM using the same scale as `int_to_f32`.
+/// Values at or above +1.0 saturate at 2^(bits-1) - 1.
+pub fn f32_to_int(v: f32, bits: u16) -> i32 {
+    let scale = int_full_scale(bits);
+    (v as f64 * scale).round().clamp(-scale, scale - 1.0) as i32
+}
+
 pub fn get_peak_level(input: &Path) -> Result<f64> {
-    let mut reader = WavReader::open(input)
-        .map_err(|e| anyhow!("Failed to open input file: {}", e))?;
+    let read_error = |e| Error::from_hound_read(input, e);
+    let mut reader = WavReader::open(input).map_err(read_error)?;
 
     let spec = reader.spec();
 
@@ -18,15 +41,14 @@ pub fn get_peak_level(input: &Path) -> Result<f64> {
     match spec.sample_format {
         SampleFormat::Float => {
             for s in reader.samples::<f32>() {
-                let v = s?;
+                let v = s.map_err(read_error)?;
                 let a = v.abs();
                 if a > peak { peak = a; }
             }
         }
         SampleFormat::Int => {
-            let max = (1i64 << (spec.bits_per_sample - 1)) as f32;
             for s in reader.samples::<i32>() {
-                let v = s? as f32 / max;
+                let v = int_to_f32(s.map_err(read_error)?, spec.bits_per_sample);
                 let a = v.abs();
                 if a > peak { peak = a; }
             }
@@ -39,12 +61,12 @@ pub fn get_peak_level(input: &Path) -> Result<f64> {
     Ok(20.0 * (peak as f64).log10())
 }
 
-#[allow(dead_code)]
 pub fn get_lufs_level(input: &Path) -> Result<f64> {
-    let mut reader = WavReader::open(input)?;
+    let read_error = |e| Error::from_hound_read(input, e);
+    let mut reader = WavReader::open(input).map_err(read_error)?;
     let spec = reader.spec();
 
-    let rate = spec.sample_rate as u32;
+    let rate = spec.sample_rate;
     let ch = spec.channels as usize;
 
     let mut meter = EbuR128::new(ch as u32, rate, ebur128::Mode::I)?;
@@ -52,22 +74,25 @@ pub fn get_lufs_level(input: &Path) -> Result<f64> {
     match spec.sample_format {
         SampleFormat::Float => {
             let mut buf: Vec<f32> = Vec::new();
-            for s in reader.samples::<f32>() { buf.push(s?); }
+            for s in reader.samples::<f32>() { buf.push(s.map_err(read_error)?); }
             meter.add_frames_f32(&buf)?;
         }
         SampleFormat::Int => {
-            let max = (1i64 << (spec.bits_per_sample - 1)) as f32;
             let mut buf: Vec<f32> = Vec::new();
-            for s in reader.samples::<i32>() { buf.push(s? as f32 / max); }
+            for s in reader.samples::<i32>() { buf.push(int_to_f32(s.map_err(read_error)?, spec.bits_per_sample)); }
             meter.add_frames_f32(&buf)?;
         }
     }
 
     let lufs = meter.loudness_global()?;
-    Ok(lufs as f64)
+    Ok(lufs)
 }
 
 pub fn write_wav(output: &Path, spec: &WavSpec, data: &[f32]) -> Result<()> {
+    write_atomically(output, |temp| write_wav_samples(temp, spec, data).map_err(|e| Error::output(output, e)))
+}
+
+fn write_wav_samples(output: &Path, spec: &WavSpec, data: &[f32]) -> hound::Result<()> {
     let file = File::create(output)?;
     let buf = BufWriter::new(file);
     let mut writer = WavWriter::new(buf, *spec)?;
@@ -80,26 +105,14 @@ pub fn write_wav(output: &Path, spec: &WavSpec, data: &[f32]) -> Result<()> {
             }
         }
         SampleFormat::Int => {
-            // Calculate the proper scaling factor based on bit depth
-            let scale_factor = match spec.bits_per_sample {
-                8 => 127.0,
-                16 => 32767.0,
-                24 => 8388607.0,
-                32 => 2147483647.0,
-                _ => (1i64 << (spec.bits_per_sample - 1)) as f32 - 1.0,
-            };
-            
-            for v in data { 
-                let clamped = v.clamp(-1.0, 1.0);
-                let scaled = (clamped * scale_factor).round();
-                
+            for v in data {
+                let scaled = f32_to_int(*v, spec.bits_per_sample);
+
                 // Write the sample with proper type based on bit depth
                 match spec.bits_per_sample {
                     8 => writer.write_sample(scaled as i8)?,
                     16 => writer.write_sample(scaled as i16)?,
-                    24 => writer.write_sample(scaled as i32)?, // hound handles 24-bit as i32
-                    32 => writer.write_sample(scaled as i32)?,
-                    _ => writer.write_sample(scaled as i32)?, // fallback
+                    _ => writer.write_sample(scaled)?, // hound handles 24-bit as i32
                 }
             }
         }
@@ -109,21 +122,297 @@ pub fn write_wav(output: &Path, spec: &WavSpec, data: &[f32]) -> Result<()> {
     Ok(())
 }
 
-#[allow(dead_code)]
+/// Write a WAV file to a stream that cannot seek, such as stdout.
+///
+/// Every sample is known up front, so the RIFF and data chunk sizes are written in the
+/// header instead of being patched afterwards. The header matches hound's byte for byte.
+/// Audio whose sizes do not fit the 32-bit RIFF fields is refused before anything is written.
+pub fn write_wav_stream(writer: impl Write, spec: &WavSpec, data: &[f32]) -> io::Result<()> {
+    let bytes_per_sample = spec.bits_per_sample.div_ceil(8) as u32;
+    let block_align = bytes_per_sample * spec.channels as u32;
+    // Same choice as hound: the older PCMWAVEFORMAT unless more than two channels or 16 bits
+    let extensible = spec.channels > 2 || spec.bits_per_sample > 16;
+    let (data_len, riff_len) = wav_chunk_sizes(data.len(), bytes_per_sample, extensible)?;
+    let mut out = BufWriter::new(writer);
+    let format_tag: u16 = match (extensible, spec.sample_format) {
+        (true, _) => 0xFFFE,
+        (false, SampleFormat::Int) => 1,
+        (false, SampleFormat::Float) => 3,
+    };
+
+    out.write_all(b"RIFF")?;
+    out.write_all(&riff_len.to_le_bytes())?;
+    out.write_all(b"WAVEfmt ")?;
+    out.write_all(&(if extensible { 40u32 } else { 16 }).to_le_bytes())?;
+    out.write_all(&format_tag.to_le_bytes())?;
+    out.write_all(&spec.channels.to_le_bytes())?;
+    out.write_all(&spec.sample_rate.to_le_bytes())?;
+    out.write_all(&(spec.sample_rate * block_align).to_le_bytes())?;
+    out.write_all(&(block_align as u16).to_le_bytes())?;
+    if extensible {
+        let channel_mask = (0..spec.channels.min(18)).fold(0u32, |mask, c| mask | 1 << c);
+        let sub_format: u32 = match spec.sample_format {
+            SampleFormat::Int => 1,
+            SampleFormat::Float => 3,
+        };
+        out.write_all(&(bytes_per_sample as u16 * 8).to_le_bytes())?;
+        out.write_all(&22u16.to_le_bytes())?;
+        out.write_all(&spec.bits_per_sample.to_le_bytes())?;
+        out.write_all(&channel_mask.to_le_bytes())?;
+        out.write_all(&sub_format.to_le_bytes())?;
+        out.write_all(&[0x00, 0x00, 0x10, 0x00, 0x80, 0x00, 0x00, 0xAA, 0x00, 0x38, 0x9B, 0x71])?;
+    } else {
+        out.write_all(&spec.bits_per_sample.to_le_bytes())?;
+    }
+    out.write_all(b"data")?;
+    out.write_all(&data_len.to_le_bytes())?;
+
+    for v in data {
+        match (spec.sample_format, spec.bits_per_sample) {
+            (SampleFormat::Float, _) => out.write_all(&v.clamp(-1.0, 1.0).to_le_bytes())?,
+            // 8-bit WAV is unsigned
+            (SampleFormat::Int, 8) => out.write_all(&[(f32_to_int(*v, 8) + 128) as u8])?,
+            (SampleFormat::Int, bits) => {
+                let bytes = f32_to_int(*v, bits).to_le_bytes();
+                out.write_all(&bytes[..bytes_per_sample as usize])?;
+            }
+        }
+    }
+    out.flush()
+}
+
+/// Sizes of the data chunk and of the whole RIFF chunk, or an error when they exceed 4 GiB
+fn wav_chunk_sizes(samples: usize, bytes_per_sample: u32, extensible: bool) -> io::Result<(u32, u32)> {
+    let header = if extensible { 60 } else { 36 };
+    u32::try_from(samples)
+        .ok()
+        .and_then(|samples| samples.checked_mul(bytes_per_sample))
+        .and_then(|data_len| Some((data_len, data_len.checked_add(header)?)))
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "audio too large for a WAV file (4 GiB at most)"))
+}
+
 pub fn read_wav_as_f32(path: &Path) -> Result<(WavSpec, Vec<f32>)> {
-    let mut reader = WavReader::open(path)?;
+    let read_error = |e| Error::from_hound_read(path, e);
+    let mut reader = WavReader::open(path).map_err(read_error)?;
     let spec = reader.spec();
 
     let mut data: Vec<f32> = Vec::new();
     match spec.sample_format {
         SampleFormat::Float => {
-            for s in reader.samples::<f32>() { data.push(s?); }
+            for s in reader.samples::<f32>() { data.push(s.map_err(read_error)?); }
         }
         SampleFormat::Int => {
-            let max = (1i64 << (spec.bits_per_sample - 1)) as f32;
-            for s in reader.samples::<i32>() { data.push(s? as f32 / max); }
+            for s in reader.samples::<i32>() { data.push(int_to_f32(s.map_err(read_error)?, spec.bits_per_sample)); }
         }
     }
     Ok((spec, data))
 }
 
+/// Copy the PCM payload of a WAV file verbatim into a new WAV file with the same spec.
+///
+/// Used when processing would leave every sample untouched, so that the output
+/// is bit-identical to the input regardless of bit depth.
+pub fn copy_wav_pcm(input: &Path, output: &Path) -> Result<()> {
+    write_atomically(output, |temp| copy_wav_samples(input, temp, output))
+}
+
+fn copy_wav_samples(input: &Path, temp: &Path, output: &Path) -> Result<()> {
+    let read_error = |e| Error::from_hound_read(input, e);
+    let write_error = |e: hound::Error| Error::output(output, e);
+    let mut reader = WavReader::open(input).map_err(read_error)?;
+    let spec = reader.spec();
+
+    let mut writer = WavWriter::create(temp, spec).map_err(write_error)?;
+
+    match spec.sample_format {
+        SampleFormat::Float => {
+            for s in reader.samples::<f32>() { writer.write_sample(s.map_err(read_error)?).map_err(write_error)?; }
+        }
+        SampleFormat::Int => {
+            for s in reader.samples::<i32>() { writer.write_sample(s.map_err(read_error)?).map_err(write_error)?; }
+        }
+    }
+
+    writer.finalize().map_err(write_error)?;
+    Ok(())
+}
+
+
+/// Remap interleaved samples from one channel count to another.
+///
+/// Downmixing to mono averages all channels, mono is copied to the first two channels,
+/// and otherwise channels are matched by position (extra ones dropped or left silent).
+pub fn remap_channels(samples: &[f32], from: usize, to: usize) -> Vec<f32> {
+    if from == to || from == 0 {
+        return samples.to_vec();
+    }
+
+    let frames = samples.len() / from;
+    let mut out = vec![0.0f32; frames * to];
+    for (frame, input) in samples.chunks_exact(from).enumerate() {
+        let output = &mut out[frame * to..(frame + 1) * to];
+        if to == 1 {
+            output[0] = input.iter().sum::<f32>() / from as f32;
+        } else if from == 1 {
+            output[0] = input[0];
+            output[1] = input[0];
+        } else {
+            let n = from.min(to);
+            output[..n].copy_from_slice(&input[..n]);
+        }
+    }
+    out
+}
+
+/// Resample interleaved samples from `from_rate` to `to_rate`, compensating for the resampler's delay
+pub fn resample(samples: &[f32], channels: usize, from_rate: usize, to_rate: usize) -> Result<Vec<f32>> {
+    if from_rate == to_rate || channels == 0 || samples.is_empty() {
+        return Ok(samples.to_vec());
+    }
+
+    let frames = samples.len() / channels;
+    let planar: Vec<Vec<f32>> = (0..channels)
+        .map(|c| samples.iter().skip(c).step_by(channels).copied().collect())
+        .collect();
+
+    let mut resampler = FftFixedIn::<f32>::new(from_rate, to_rate, 1024, 2, channels)?;
+    let delay = resampler.output_delay();
+    let expected = (frames as u64 * to_rate as u64 / from_rate as u64) as usize;
+
+    let mut out: Vec<Vec<f32>> = vec![Vec::with_capacity(expected + delay); channels];
+    let mut pos = 0;
+    while out[0].len() < expected + delay {
+        let needed = resampler.input_frames_next();
+        let block = if pos + needed <= frames {
+            let chunk: Vec<&[f32]> = planar.iter().map(|c| &c[pos..pos + needed]).collect();
+            pos += needed;
+            resampler.process(&chunk, None)?
+        } else if pos < frames {
+            let chunk: Vec<&[f32]> = planar.iter().map(|c| &c[pos..]).collect();
+            pos = frames;
+            resampler.process_partial(Some(&chunk), None)?
+        } else {
+            // Flush the samples still held back by the resampler's delay
+            resampler.process_partial::<&[f32]>(None, None)?
+        };
+        for (channel, data) in out.iter_mut().zip(block) {
+            channel.extend_from_slice(&data);
+        }
+    }
+
+    let mut interleaved = Vec::with_capacity(expected * channels);
+    for frame in delay..delay + expected {
+        for channel in &out {
+            interleaved.push(channel[frame]);
+        }
+    }
+    Ok(interleaved)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::temp_path;
+
+    /// Full-scale extremes and a spread of values in between
+    fn test_samples(bits: u16) -> Vec<i32> {
+        let max = ((1i64 << (bits - 1)) - 1) as i32;
+        let min = -max - 1;
+        let mut samples = vec![min, min + 1, -1, 0, 1, max - 1, max];
+        samples.extend((1..64).map(|i| (min as i64 + (max as i64 - min as i64) * i / 64) as i32));
+        samples
+    }
+
+    fn write_int_wav(path: &Path, bits: u16, samples: &[i32]) {
+        let spec = WavSpec { channels: 1, sample_rate: 48000, bits_per_sample: bits, sample_format: SampleFormat::Int };
+        let mut writer = WavWriter::create(path, spec).unwrap();
+        for &s in samples {
+            match bits {
+                8 => writer.write_sample(s as i8).unwrap(),
+                16 => writer.write_sample(s as i16).unwrap(),
+                _ => writer.write_sample(s).unwrap(),
+            }
+        }
+        writer.finalize().unwrap();
+    }
+
+    fn read_int_wav(path: &Path) -> Vec<i32> {
+        WavReader::open(path).unwrap().samples::<i32>().map(|s| s.unwrap()).collect()
+    }
+
+    #[test]
+    fn f32_round_trip_is_exact_up_to_24_bits() {
+        for bits in [8, 16, 24] {
+            for s in test_samples(bits) {
+                assert_eq!(f32_to_int(int_to_f32(s, bits), bits), s, "{}-bit sample {}", bits, s);
+            }
+        }
+    }
+
+    #[test]
+    fn f32_round_trip_keeps_24_significant_bits_at_32_bits() {
+        assert_ne!(f32_to_int(int_to_f32(123456789, 32), 32), 123456789);
+        for s in test_samples(32) {
+            let back = f32_to_int(int_to_f32(s, 32), 32);
+            assert!((back as i64 - s as i64).abs() <= 128, "32-bit sample {} came back as {}", s, back);
+        }
+    }
+
+    #[test]
+    fn wav_through_f32_round_trips_up_to_24_bits() {
+        for bits in [8, 16, 24] {
+            let (input, output) = (temp_path(&format!("f32-in{}.wav", bits)), temp_path(&format!("f32-out{}.wav", bits)));
+            let samples = test_samples(bits);
+            write_int_wav(&input, bits, &samples);
+            let (spec, data) = read_wav_as_f32(&input).unwrap();
+            write_wav(&output, &spec, &data).unwrap();
+            assert_eq!(read_int_wav(&output), samples, "{}-bit", bits);
+            let _ = std::fs::remove_file(&input);
+            let _ = std::fs::remove_file(&output);
+        }
+    }
+
+    #[test]
+    fn streamed_wav_matches_hound_byte_for_byte() {
+        let specs = [
+            (1, 8, SampleFormat::Int),
+            (2, 16, SampleFormat::Int),
+            (2, 24, SampleFormat::Int),
+            (6, 16, SampleFormat::Int),
+            (2, 32, SampleFormat::Float),
+            (1, 32, SampleFormat::Int),
+        ];
+        for (channels, bits_per_sample, sample_format) in specs {
+            let spec = WavSpec { channels, sample_rate: 44100, bits_per_sample, sample_format };
+            let data: Vec<f32> = (0..channels as usize * 50).map(|i| ((i as f32) * 0.37).sin() * 0.9).collect();
+            let path = temp_path(&format!("stream-{}-{}-{:?}.wav", channels, bits_per_sample, sample_format));
+            write_wav(&path, &spec, &data).unwrap();
+            let mut streamed = Vec::new();
+            write_wav_stream(&mut streamed, &spec, &data).unwrap();
+            assert_eq!(streamed, std::fs::read(&path).unwrap(), "{:?}", spec);
+            let _ = std::fs::remove_file(&path);
+        }
+    }
+
+    #[test]
+    fn wav_chunk_sizes_refuse_more_than_4_gib() {
+        assert_eq!(wav_chunk_sizes(1000, 2, false).unwrap(), (2000, 2036));
+        assert_eq!(wav_chunk_sizes(1000, 3, true).unwrap(), (3000, 3060));
+        assert!(wav_chunk_sizes(u32::MAX as usize / 2, 4, false).is_err());
+        assert!(wav_chunk_sizes((u32::MAX - 30) as usize, 1, false).is_err());
+        assert!(wav_chunk_sizes(u32::MAX as usize + 1, 1, false).is_err());
+    }
+
+    #[test]
+    fn wav_passthrough_is_bit_exact_at_every_depth() {
+        for bits in [8, 16, 24, 32] {
+            let (input, output) = (temp_path(&format!("copy-in{}.wav", bits)), temp_path(&format!("copy-out{}.wav", bits)));
+            let samples = test_samples(bits);
+            write_int_wav(&input, bits, &samples);
+            copy_wav_pcm(&input, &output).unwrap();
+            assert_eq!(std::fs::read(&output).unwrap(), std::fs::read(&input).unwrap(), "{}-bit", bits);
+            let _ = std::fs::remove_file(&input);
+            let _ = std::fs::remove_file(&output);
+        }
+    }
+}
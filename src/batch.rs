@@ -0,0 +1,377 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+use crate::atomic::write_atomically;
+use crate::error::{Error, Result};
+use crate::interrupt;
+use crate::normalizer::{normalize_to_template, NormalizeOptions, OutputReport, Target};
+use crate::template::OutputTemplate;
+
+/// Start of the name of the file recording an interrupted batch, followed by `Batch::key`
+pub const RESUME_FILE_PREFIX: &str = ".audio_normalizer-resume-";
+
+/// Where a batch writes the output of each input
+#[derive(Debug, Clone)]
+pub enum BatchOutput {
+    /// Into a directory, under the input's file name
+    Dir(PathBuf),
+    /// To the path rendered from a template for each input
+    Template(OutputTemplate),
+    /// Over the input itself
+    InPlace,
+}
+
+/// Many inputs normalized in parallel to one target, resumable after an interrupt
+#[derive(Debug, Clone)]
+pub struct Batch {
+    pub inputs: Vec<PathBuf>,
+    pub output: BatchOutput,
+    pub target: Target,
+    pub options: NormalizeOptions,
+}
+
+/// How one input of a batch ended, as passed to the callback of `Batch::run`
+#[derive(Debug)]
+pub enum FileOutcome<'a> {
+    /// Completed by the interrupted run being resumed, so not processed again
+    Resumed,
+    /// Normalized into these outputs
+    Normalized(&'a [OutputReport]),
+    /// Failed with this error
+    Failed(&'a Error),
+}
+
+/// What a batch run did with its inputs
+#[derive(Debug)]
+pub struct BatchSummary {
+    pub total: usize,
+    /// Inputs skipped because the resumed run had completed them
+    pub resumed: usize,
+    pub failed: Vec<(PathBuf, Error)>,
+    /// Inputs not processed because the run was interrupted
+    pub pending: Vec<PathBuf>,
+    /// Where the state of an interrupted run was saved for a later `--resume`
+    pub state_file: Option<PathBuf>,
+}
+
+impl BatchSummary {
+    pub fn succeeded(&self) -> usize {
+        self.total - self.failed.len() - self.pending.len()
+    }
+
+    pub fn interrupted(&self) -> bool {
+        !self.pending.is_empty()
+    }
+}
+
+impl Batch {
+    /// The output template for one input
+    pub fn output_for(&self, input: &Path) -> OutputTemplate {
+        match &self.output {
+            BatchOutput::Dir(dir) => OutputTemplate::literal(&dir.join(input.file_name().unwrap_or_default())),
+            BatchOutput::Template(template) => template.clone(),
+            BatchOutput::InPlace => OutputTemplate::literal(input),
+        }
+    }
+
+    /// Identifies the batch across runs: a hash of its inputs, output, target and options.
+    ///
+    /// The state of an interrupted run is only resumed by a batch with the same key, so
+    /// unrelated batches sharing a directory keep their state apart.
+    pub fn key(&self) -> String {
+        let options = NormalizeOptions { dry_run: false, claims: None, ..self.options.clone() };
+        let description = format!("{:?}\n{:?}\n{:?}\n{:?}", self.inputs, self.output, self.target, options);
+        // FNV-1a, which unlike `DefaultHasher` is the same in every build
+        let hash = description
+            .bytes()
+            .fold(0xcbf2_9ce4_8422_2325u64, |hash, byte| (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3));
+        format!("{:016x}", hash)
+    }
+
+    /// Where the state of an interrupted run is kept: the output directory, or the working
+    /// directory with a template or in place, under a name ending in the batch's key
+    pub fn resume_file(&self) -> PathBuf {
+        let name = format!("{}{}.json", RESUME_FILE_PREFIX, self.key());
+        match &self.output {
+            BatchOutput::Dir(dir) => dir.join(name),
+            _ => PathBuf::from(name),
+        }
+    }
+
+    /// Normalize every input in parallel, skipping those that `resume` (the state of an
+    /// interrupted run) records as completed.
+    ///
+    /// `on_file` is called once for each input as it ends, except for inputs stopped by an
+    /// interrupt; an error it returns fails the input. A failed input does not stop the others.
+    /// When interrupted, the run saves its state to `resume_file` (unless it is a dry run);
+    /// a resumed run that completes deletes it.
+    pub fn run<F>(&self, resume: Option<ResumeState>, on_file: F) -> Result<BatchSummary>
+    where
+        F: Fn(&Path, FileOutcome) -> Result<()> + Sync,
+    {
+        if let (BatchOutput::Dir(dir), false) = (&self.output, self.options.dry_run) {
+            std::fs::create_dir_all(dir).map_err(|e| Error::output(dir, e))?;
+        }
+        let options = NormalizeOptions {
+            in_place: matches!(self.output, BatchOutput::InPlace),
+            // Inputs of the batch that render to the same output name collide with each other
+            claims: Some(Arc::default()),
+            ..self.options.clone()
+        };
+
+        let resuming = resume.is_some();
+        let mut state = ResumeState {
+            batch: self.key(),
+            completed: resume.map(|state| state.completed).unwrap_or_default(),
+            ..Default::default()
+        };
+        let (resumed, inputs): (Vec<&PathBuf>, Vec<&PathBuf>) =
+            self.inputs.iter().partition(|input| state.completed.contains(input));
+        for input in &resumed {
+            let _ = on_file(input, FileOutcome::Resumed);
+        }
+
+        let results: Vec<(&PathBuf, Result<()>)> = inputs
+            .par_iter()
+            .map(|&input| {
+                if interrupt::is_interrupted() {
+                    return (input, Err(Error::Interrupted));
+                }
+                let result = match normalize_to_template(input, &self.output_for(input), self.target, &options) {
+                    Ok(reports) => on_file(input, FileOutcome::Normalized(&reports)),
+                    Err(Error::Interrupted) => Err(Error::Interrupted),
+                    Err(e) => {
+                        let _ = on_file(input, FileOutcome::Failed(&e));
+                        Err(e)
+                    }
+                };
+                (input, result)
+            })
+            .collect();
+
+        let mut failed = Vec::new();
+        let mut pending = Vec::new();
+        for (input, result) in results {
+            match result {
+                Ok(()) => state.completed.push(input.clone()),
+                Err(Error::Interrupted) => pending.push(input.clone()),
+                Err(e) => failed.push((input.clone(), e)),
+            }
+        }
+
+        let resume_file = self.resume_file();
+        let mut state_file = None;
+        if pending.is_empty() {
+            if resuming {
+                ResumeState::remove(&resume_file);
+            }
+        } else if !self.options.dry_run {
+            state.failed = failed.iter().map(|(input, _)| input.clone()).collect();
+            state.pending = pending.clone();
+            state.save(&resume_file)?;
+            state_file = Some(resume_file);
+        }
+        Ok(BatchSummary { total: self.inputs.len(), resumed: resumed.len(), failed, pending, state_file })
+    }
+}
+
+/// The inputs of an interrupted batch by outcome, read back by `--resume`
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct ResumeState {
+    /// `Batch::key` of the interrupted batch
+    pub batch: String,
+    pub completed: Vec<PathBuf>,
+    pub failed: Vec<PathBuf>,
+    pub pending: Vec<PathBuf>,
+}
+
+impl ResumeState {
+    /// Read the state of the interrupted run of the batch with `key`, or start afresh if there is none.
+    ///
+    /// A state saved by another batch is refused rather than resumed.
+    pub fn load(path: &Path, key: &str) -> Result<ResumeState> {
+        let state: ResumeState = match std::fs::read_to_string(path) {
+            Ok(text) => serde_json::from_str(&text)
+                .map_err(|e| Error::Usage(format!("cannot resume from {}: {}", path.display(), e)))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                warn!("no interrupted batch to resume ({} not found); processing every input", path.display());
+                return Ok(ResumeState { batch: key.to_string(), ..Default::default() });
+            }
+            Err(e) => return Err(Error::Input { path: path.to_path_buf(), source: e }),
+        };
+        if state.batch != key {
+            return Err(Error::Usage(format!(
+                "cannot resume from {}: it records another batch (different inputs, output or options)",
+                path.display()
+            )));
+        }
+        Ok(state)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let json = serde_json::to_string_pretty(self).map_err(|e| Error::output(path, e))?;
+        write_atomically(path, |temp| std::fs::write(temp, json).map_err(|e| Error::output(path, e)))
+    }
+
+    pub fn remove(path: &Path) {
+        if let Err(e) = std::fs::remove_file(path) {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                warn!("cannot remove {}: {}", path.display(), e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::normalizer::CollisionPolicy;
+    use crate::test_util::temp_dir;
+
+    /// Write one second of an 8 kHz mono sawtooth
+    fn tone(path: &Path) {
+        let spec = hound::WavSpec { channels: 1, sample_rate: 8000, bits_per_sample: 16, sample_format: hound::SampleFormat::Int };
+        let mut writer = hound::WavWriter::create(path, spec).unwrap();
+        for i in 0..8000 {
+            writer.write_sample(((i % 40) * 200 - 4000) as i16).unwrap();
+        }
+        writer.finalize().unwrap();
+    }
+
+    #[test]
+    fn inputs_rendering_to_one_name_collide_even_when_overwriting() {
+        let dir = temp_dir("batch-same-name");
+        let (a, b, output) = (dir.join("a.wav"), dir.join("b.wav"), dir.join("same.wav"));
+        tone(&a);
+        tone(&b);
+        std::fs::write(&output, b"old").unwrap();
+        let batch = Batch {
+            inputs: vec![a, b],
+            output: BatchOutput::Template(OutputTemplate::literal(&output)),
+            target: Target::Peak(-6.0),
+            options: NormalizeOptions { on_collision: CollisionPolicy::Overwrite, ..Default::default() },
+        };
+
+        let summary = batch.run(None, |_, _| Ok(())).unwrap();
+        assert_eq!((summary.succeeded(), summary.failed.len()), (1, 1));
+        assert!(matches!(summary.failed[0].1, Error::Output { .. }), "{}", summary.failed[0].1);
+        assert!(hound::WavReader::open(&output).is_ok());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    fn batch(inputs: &[&str], output: BatchOutput) -> Batch {
+        Batch {
+            inputs: inputs.iter().map(PathBuf::from).collect(),
+            output,
+            target: Target::Lufs(-16.0),
+            options: NormalizeOptions::default(),
+        }
+    }
+
+    #[test]
+    fn resume_files_are_kept_apart_by_batch() {
+        let template = || BatchOutput::Template("{stem}_norm.{ext}".parse().unwrap());
+        let a = batch(&["a.wav", "b.wav"], template());
+        assert_eq!(a.key(), batch(&["a.wav", "b.wav"], template()).key());
+        assert_eq!(a.resume_file().parent(), Some(Path::new("")));
+
+        let others = [
+            batch(&["a.wav", "c.wav"], template()),
+            batch(&["a.wav", "b.wav"], BatchOutput::InPlace),
+            Batch { target: Target::Lufs(-14.0), ..batch(&["a.wav", "b.wav"], template()) },
+            Batch { options: NormalizeOptions { fade_in: 1.0, ..Default::default() }, ..batch(&["a.wav", "b.wav"], template()) },
+        ];
+        for other in others {
+            assert_ne!(a.resume_file(), other.resume_file(), "{:?}", other);
+        }
+        // Neither a dry run nor the claims of a run change which batch it is
+        let options = NormalizeOptions { dry_run: true, claims: Some(Arc::default()), ..Default::default() };
+        assert_eq!(a.key(), Batch { options, ..batch(&["a.wav", "b.wav"], template()) }.key());
+    }
+
+    #[test]
+    fn a_resume_file_of_another_batch_is_refused() {
+        let dir = temp_dir("resume-other-batch");
+        let path = dir.join("state.json");
+        ResumeState { batch: "0123456789abcdef".to_string(), ..Default::default() }.save(&path).unwrap();
+        let error = ResumeState::load(&path, "fedcba9876543210").err().unwrap();
+        assert_eq!(error.exit_code(), 2, "{}", error);
+        assert!(ResumeState::load(&path, "0123456789abcdef").is_ok());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn resume_state_round_trips() {
+        let dir = temp_dir("resume-state");
+        let path = dir.join("state.json");
+        let state = ResumeState {
+            batch: "0123456789abcdef".to_string(),
+            completed: vec![PathBuf::from("a.wav")],
+            failed: vec![PathBuf::from("b.wav")],
+            pending: vec![PathBuf::from("c d.wav"), PathBuf::from("e.wav")],
+        };
+        state.save(&path).unwrap();
+        let loaded = ResumeState::load(&path, &state.batch).unwrap();
+        assert_eq!((loaded.completed, loaded.failed, loaded.pending), (state.completed, state.failed, state.pending));
+
+        ResumeState::remove(&path);
+        assert!(!path.exists());
+        ResumeState::remove(&path);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn a_missing_resume_file_starts_afresh() {
+        let dir = temp_dir("resume-missing");
+        let state = ResumeState::load(&dir.join("state.json"), "0123456789abcdef").unwrap();
+        assert!(state.completed.is_empty() && state.failed.is_empty() && state.pending.is_empty());
+        assert_eq!(state.batch, "0123456789abcdef");
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn a_damaged_resume_file_is_a_usage_error() {
+        let dir = temp_dir("resume-damaged");
+        let path = dir.join("state.json");
+        std::fs::write(&path, "{\"completed\": [").unwrap();
+        let error = ResumeState::load(&path, "0123456789abcdef").err().unwrap();
+        assert_eq!(error.exit_code(), 2, "{}", error);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn a_resumed_batch_skips_completed_inputs() {
+        let dir = temp_dir("resume-batch");
+        let (done, todo, out) = (dir.join("done.wav"), dir.join("todo.wav"), dir.join("out"));
+        tone(&todo);
+        let batch = Batch {
+            inputs: vec![done.clone(), todo.clone()],
+            output: BatchOutput::Dir(out.clone()),
+            target: Target::Peak(-6.0),
+            options: NormalizeOptions::default(),
+        };
+        // `done.wav` does not exist, so the batch fails unless it is skipped
+        std::fs::create_dir_all(&out).unwrap();
+        let state = ResumeState { batch: batch.key(), completed: vec![done.clone()], failed: Vec::new(), pending: vec![todo.clone()] };
+        state.save(&batch.resume_file()).unwrap();
+
+        let outcomes = std::sync::Mutex::new(Vec::new());
+        let summary = batch
+            .run(Some(ResumeState::load(&batch.resume_file(), &batch.key()).unwrap()), |input, outcome| {
+                let resumed = matches!(outcome, FileOutcome::Resumed);
+                outcomes.lock().unwrap().push((input.to_path_buf(), resumed));
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!((summary.total, summary.resumed, summary.succeeded()), (2, 1, 2));
+        assert!(summary.failed.is_empty() && !summary.interrupted());
+        let mut outcomes = outcomes.into_inner().unwrap();
+        outcomes.sort();
+        assert_eq!(outcomes, vec![(done, true), (todo, false)]);
+        assert!(out.join("todo.wav").exists() && !out.join("done.wav").exists());
+        assert!(!batch.resume_file().exists());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
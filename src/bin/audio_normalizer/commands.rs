@@ -0,0 +1,235 @@
+//! The commands that decode and normalize audio
+
+use crate::progress::{finish_file, finish_progress, suspend_progress, STDOUT_IS_DATA};
+use crate::report::{print_compliance, print_damaged_regions, print_lufs_report, print_plan, print_verification, CheckSummary, CheckedFile};
+use crate::{BatchCommand, CheckCommand, DecodeArgs, NormalizeArgs, NormalizeCommand};
+use anyhow::Result;
+use audio_normalizer::{
+    compliance, is_stdio, normalizer, Batch, BatchOutput, Error, FileOutcome, MultiFormatProcessor, NormalizeOptions,
+    OutputReport, OutputTemplate, ResumeState, Target, TrackSelector,
+};
+use rayon::prelude::*;
+use std::path::Path;
+use std::sync::atomic::Ordering;
+use tracing::{debug, error, info};
+
+pub(crate) fn run_analysis(input: &Path, decode: &DecodeArgs, peak: bool, lufs: bool) -> Result<()> {
+    info!("Analyzing {}", input.display());
+    let audio_data = MultiFormatProcessor::decode_audio_with_options(input, &decode.options())?;
+    finish_progress();
+    if peak {
+        println!("Peak level: {:.2} dB", audio_data.peak_db());
+    }
+    if lufs {
+        println!("LUFS level: {:.2} LUFS", audio_data.integrated_lufs()?);
+    }
+    print_damaged_regions(&audio_data);
+    Ok(())
+}
+
+pub(crate) fn run_normalize_command(cmd: &NormalizeCommand) -> Result<()> {
+    // Without --in-place or --output-template, clap requires OUTPUT
+    if let Some(output) = &cmd.output {
+        return run_normalize(&cmd.input, output, &cmd.normalize, &cmd.decode, cmd.all_tracks);
+    }
+    STDOUT_IS_DATA.store(cmd.normalize.json, Ordering::Relaxed);
+    let (target, mut options) = cmd.normalize.resolve(&cmd.decode)?;
+    if let Some(template) = &cmd.output_template {
+        return process_normalization(&cmd.input, template, target, &cmd.normalize, &options);
+    }
+    if is_stdio(&cmd.input) {
+        return Err(Error::Usage("--in-place cannot be used when reading from stdin".to_string()).into());
+    }
+
+    options.in_place = true;
+    process_normalization(&cmd.input, &OutputTemplate::literal(&cmd.input), target, &cmd.normalize, &options)
+}
+
+pub(crate) fn run_normalize(input: &Path, output: &Path, normalize: &NormalizeArgs, decode: &DecodeArgs, all_tracks: bool) -> Result<()> {
+    STDOUT_IS_DATA.store(normalize.stdout_is_data(output), Ordering::Relaxed);
+    if all_tracks && (is_stdio(input) || is_stdio(output)) {
+        return Err(Error::Usage("--all-tracks cannot read from stdin or write to stdout".to_string()).into());
+    }
+
+    let (target, options) = normalize.resolve(decode)?;
+    if !all_tracks {
+        return process_normalization(input, &OutputTemplate::literal(output), target, normalize, &options);
+    }
+
+    for track in MultiFormatProcessor::list_tracks(input)? {
+        let mut options = options.clone();
+        options.decode.track = Some(TrackSelector::Index(track.index));
+        let track_output = MultiFormatProcessor::suffixed_output_path(output, &format!("track{}", track.index));
+        process_normalization(input, &OutputTemplate::literal(&track_output), target, normalize, &options)?;
+    }
+    Ok(())
+}
+
+pub(crate) fn run_batch(cmd: &BatchCommand) -> Result<()> {
+    STDOUT_IS_DATA.store(cmd.normalize.json, Ordering::Relaxed);
+    let (target, options) = cmd.normalize.resolve(&cmd.decode)?;
+    let output = match (&cmd.output_template, &cmd.output_dir) {
+        (Some(template), _) => BatchOutput::Template(template.clone()),
+        (None, Some(dir)) => BatchOutput::Dir(dir.clone()),
+        (None, None) => BatchOutput::InPlace,
+    };
+    let batch = Batch { inputs: cmd.inputs.clone(), output, target, options };
+
+    let resume = if cmd.resume { Some(ResumeState::load(&batch.resume_file(), &batch.key())?) } else { None };
+    if let Some(state) = &resume {
+        let completed = cmd.inputs.iter().filter(|input| state.completed.contains(input)).count();
+        status!("Resuming batch: {} of {} inputs already completed", completed, cmd.inputs.len());
+    }
+
+    let summary = batch.run(resume, |input, outcome| {
+        let result = match outcome {
+            FileOutcome::Resumed => Ok(()),
+            FileOutcome::Normalized(reports) => print_reports(input, reports, target, &cmd.normalize, &batch.options),
+            FileOutcome::Failed(_) => {
+                finish_file(input, false);
+                return Ok(());
+            }
+        };
+        finish_file(input, result.is_ok());
+        result
+    })?;
+
+    let (succeeded, failed) = (summary.succeeded(), summary.failed.len());
+    for (input, e) in summary.failed {
+        let e = anyhow::Error::from(e);
+        error!(input = %input.display(), "{}: {:#}", input.display(), e);
+    }
+    if !summary.pending.is_empty() {
+        status!("Batch interrupted: {} succeeded, {} failed, {} not processed", succeeded, failed, summary.pending.len());
+        for input in &summary.pending {
+            info!(input = %input.display(), "not processed: {}", input.display());
+        }
+        if let Some(state_file) = &summary.state_file {
+            status!("Run the same command with --resume to process the rest (state saved in {})", state_file.display());
+        }
+        return Err(Error::Interrupted.into());
+    }
+
+    status!("Batch completed: {} succeeded, {} failed", succeeded, failed);
+    if failed == 0 {
+        Ok(())
+    } else {
+        Err(Error::Batch { failed, total: summary.total }.into())
+    }
+}
+
+pub(crate) fn run_check(cmd: &CheckCommand) -> Result<()> {
+    let options = cmd.decode.options();
+    let results: Vec<_> = cmd
+        .inputs
+        .par_iter()
+        .map(|input| {
+            if audio_normalizer::is_interrupted() {
+                return Err(Error::Interrupted);
+            }
+            let result = compliance::check(input, cmd.spec, &options);
+            if !matches!(result, Err(Error::Interrupted)) {
+                finish_file(input, result.is_ok());
+            }
+            result
+        })
+        .collect();
+    finish_progress();
+
+    let pending = results.iter().filter(|result| matches!(result, Err(Error::Interrupted))).count();
+    if pending > 0 {
+        println!("Check interrupted: {} checked, {} not checked", cmd.inputs.len() - pending, pending);
+        return Err(Error::Interrupted.into());
+    }
+
+    let mut failed = 0;
+    let mut files = Vec::new();
+    for (input, result) in cmd.inputs.iter().zip(results) {
+        match result {
+            Ok(report) => {
+                failed += usize::from(!report.passed());
+                if !cmd.json {
+                    print_compliance(&report);
+                }
+                files.push(CheckedFile::Report(report));
+            }
+            Err(e) => {
+                failed += 1;
+                let e = anyhow::Error::from(e);
+                error!(input = %input.display(), "{}: {:#}", input.display(), e);
+                files.push(CheckedFile::Failed { path: input, error: format!("{:#}", e) });
+            }
+        }
+    }
+
+    if cmd.json {
+        let summary = CheckSummary { spec: cmd.spec, passed: failed == 0, files };
+        println!("{}", serde_json::to_string_pretty(&summary)?);
+    } else {
+        println!("Check completed ({}): {} passed, {} failed", cmd.spec, cmd.inputs.len() - failed, failed);
+    }
+
+    if failed == 0 {
+        Ok(())
+    } else {
+        Err(Error::NonCompliant { failed, total: cmd.inputs.len(), spec: cmd.spec.to_string() }.into())
+    }
+}
+
+fn process_normalization(
+    input: &Path,
+    output: &OutputTemplate,
+    target: Target,
+    normalize: &NormalizeArgs,
+    options: &NormalizeOptions,
+) -> Result<()> {
+    debug!("Input file: {}", input.display());
+    debug!("Output file: {}", output);
+
+    let reports = normalizer::normalize_to_template(input, output, target, options)?;
+    Ok(print_reports(input, &reports, target, normalize, options)?)
+}
+
+/// Report the outputs written for one input, or the plan of a dry run
+fn print_reports(
+    input: &Path,
+    reports: &[OutputReport],
+    target: Target,
+    normalize: &NormalizeArgs,
+    options: &NormalizeOptions,
+) -> audio_normalizer::Result<()> {
+    let (fade_in, fade_out, fade_curve) = (normalize.fade_in, normalize.fade_out, normalize.fade_curve);
+
+    let mut written = false;
+    for report in reports {
+        if options.dry_run {
+            suspend_progress(|| print_plan(input, report, target, normalize.json))?;
+            continue;
+        }
+        let Some(normalized) = &report.normalize else {
+            status!("Skipped {}: {} already exists", input.display(), report.path.display());
+            continue;
+        };
+        written = true;
+        match target {
+            Target::Lufs(target_lufs) => {
+                print_lufs_report(normalized);
+                status!("LUFS normalization completed: {} -> {} (target: {:.2} LUFS)",
+                      input.display(), report.path.display(), target_lufs);
+            }
+            Target::Peak(max_peak) => {
+                status!("Peak normalization completed: {} -> {} (target: {:.2} dB)",
+                      input.display(), report.path.display(), max_peak);
+            }
+        }
+        if let Some(verification) = &report.verification {
+            print_verification(verification, target);
+        }
+    }
+
+    if written && (fade_in > 0.0 || fade_out > 0.0) {
+        status!("Applied fades: in={:.2}s, out={:.2}s, curve={}", fade_in, fade_out, fade_curve);
+    }
+
+    Ok(())
+}
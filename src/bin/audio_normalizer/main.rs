@@ -0,0 +1,884 @@
+//! Command-line interface: parses the arguments and dispatches to the commands
+
+#[macro_use]
+mod progress;
+mod commands;
+mod report;
+
+use anyhow::Result;
+use audio_normalizer::{
+    is_stdio, log_file_writer, remove_temp_files, CollisionPolicy, Config, DecodeErrorPolicy, DecodeOptions, Error, FadeCurve,
+    FormatChangePolicy, LogRotation, NormalizeOptions, OutputTemplate, Preset, ProgressLayer, RawFormat,
+    RawSampleFormat, Spec, StageSpec, Target, TrackSelector, PROGRESS_TARGET,
+};
+use audio_normalizer::config::{DecodeDefaults, NormalizeDefaults};
+use clap::builder::FalseyValueParser;
+use clap::parser::ValueSource;
+use clap::{ArgMatches, Args, CommandFactory, FromArgMatches, Parser, Subcommand};
+use commands::{run_analysis, run_batch, run_check, run_normalize, run_normalize_command};
+use progress::{finish_progress, start_progress, suspend_progress, update_progress, ProgressAwareStderr, ProgressMode};
+use report::{print_formats, print_info, print_presets, print_tracks};
+use std::collections::BTreeMap;
+use std::io::IsTerminal;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use tracing::{debug, info, warn};
+use tracing_appender::non_blocking::WorkerGuard;
+use tracing_subscriber::fmt::MakeWriter;
+use tracing_subscriber::filter::{LevelFilter, Targets};
+use tracing_subscriber::{fmt, prelude::*, Layer, Registry};
+
+/// Largest `--log-max-size` (1 TB), which keeps the size in bytes well within a `u64`
+const MAX_LOG_SIZE_MB: u64 = 1 << 20;
+
+#[derive(Parser)]
+#[command(
+    name = "audio_normalizer",
+    about = "A command-line tool for audio normalization with fade effects",
+    version = "2.0.0",
+    allow_negative_numbers = true
+)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Enable verbose output (debug level logging)
+    #[arg(short, long, global = true)]
+    verbose: bool,
+
+    /// Enable quiet mode (error level logging only)
+    #[arg(short, long, global = true)]
+    quiet: bool,
+
+    /// List the formats available for reading and writing
+    #[arg(long = "list-formats")]
+    list_formats: bool,
+
+    /// List the built-in presets and those defined in configuration files
+    #[arg(long = "list-presets")]
+    list_presets: bool,
+
+    /// Also write logs to this file, at info level (debug with --verbose) whatever --quiet says
+    #[arg(long = "log-file", value_name = "PATH", global = true)]
+    log_file: Option<PathBuf>,
+
+    /// When to start a new log file
+    #[arg(long = "log-rotation", value_enum, value_name = "WHEN", default_value = "daily", global = true, requires = "log_file")]
+    log_rotation: LogRotation,
+
+    /// Size at which `--log-rotation size` starts a new file, in MB
+    #[arg(long = "log-max-size", value_name = "MB", default_value = "10", global = true, requires = "log_file",
+          value_parser = clap::value_parser!(u64).range(1..=MAX_LOG_SIZE_MB))]
+    log_max_size: u64,
+
+    /// Number of rotated log files to keep
+    #[arg(long = "log-keep", value_name = "N", default_value = "7", global = true, requires = "log_file")]
+    log_keep: usize,
+
+    /// How to report progress: a bar, periodic log lines, or JSON events on stderr
+    #[arg(long, value_enum, value_name = "MODE", default_value = "auto", global = true)]
+    progress: ProgressMode,
+
+    /// Log format for stderr and --log-file
+    #[arg(long = "log-format", value_enum, value_name = "FORMAT", default_value = "text", global = true)]
+    log_format: LogFormat,
+
+    /// Read settings from this file instead of the user and project configuration files
+    #[arg(long, value_name = "FILE", global = true, env = "AUDIO_NORMALIZER_CONFIG")]
+    config: Option<PathBuf>,
+
+    #[command(flatten)]
+    legacy: LegacyArgs,
+}
+
+impl Cli {
+    fn progress_mode(&self) -> ProgressMode {
+        match self.progress {
+            ProgressMode::Auto if self.quiet => ProgressMode::Off,
+            ProgressMode::Auto if std::io::stderr().is_terminal() => ProgressMode::Bar,
+            ProgressMode::Auto => ProgressMode::Log,
+            mode => mode,
+        }
+    }
+}
+
+/// How log lines are written
+#[derive(Clone, Copy, clap::ValueEnum)]
+enum LogFormat {
+    /// Human-readable lines
+    Text,
+    /// One JSON object per line, with the event and per-file fields at the top level
+    Json,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Normalize a file to a peak or loudness target
+    #[command(allow_negative_numbers = true)]
+    Normalize(NormalizeCommand),
+    /// Measure peak level and integrated loudness
+    Analyze(AnalyzeCommand),
+    /// Show the peak level
+    Peak(AnalyzeCommand),
+    /// Show the integrated loudness (LUFS)
+    #[command(alias = "measure-lufs")]
+    Lufs(AnalyzeCommand),
+    /// Show container, codec, format, duration, gapless info and tags without decoding
+    Info {
+        /// Input audio file
+        #[arg(value_name = "INPUT")]
+        input: PathBuf,
+
+        /// Print the report as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// List the audio tracks of a file
+    Tracks {
+        /// Input audio file
+        #[arg(value_name = "INPUT")]
+        input: PathBuf,
+    },
+    /// Normalize many files in parallel into an output directory
+    #[command(allow_negative_numbers = true)]
+    Batch(BatchCommand),
+    /// Check files against a loudness delivery spec; exits non-zero if any file fails
+    Check(CheckCommand),
+    /// Run a batch job defined in a configuration file
+    #[command(allow_negative_numbers = true)]
+    Job(JobCommand),
+}
+
+#[derive(Args)]
+struct NormalizeCommand {
+    /// Input audio file
+    #[arg(value_name = "INPUT")]
+    input: PathBuf,
+
+    /// Output audio file
+    #[arg(value_name = "OUTPUT", required_unless_present_any = ["in_place", "output_template"])]
+    output: Option<PathBuf>,
+
+    /// Replace INPUT with the normalized audio instead of writing OUTPUT
+    #[arg(long = "in-place", conflicts_with_all = ["output", "all_tracks"])]
+    in_place: bool,
+
+    /// Output path built from INPUT, e.g. `{dir}/normalized/{stem}.wav` (see README for placeholders)
+    #[arg(long = "output-template", value_name = "TEMPLATE", conflicts_with_all = ["output", "in_place", "all_tracks"])]
+    output_template: Option<OutputTemplate>,
+
+    #[command(flatten)]
+    normalize: NormalizeArgs,
+
+    #[command(flatten)]
+    decode: DecodeArgs,
+
+    /// Process every audio track into a separate output (OUTPUT with `.trackN` before the extension)
+    #[arg(long = "all-tracks", conflicts_with = "track")]
+    all_tracks: bool,
+}
+
+#[derive(Args)]
+struct AnalyzeCommand {
+    /// Input audio file
+    #[arg(value_name = "INPUT")]
+    input: PathBuf,
+
+    #[command(flatten)]
+    decode: DecodeArgs,
+}
+
+#[derive(Args)]
+struct BatchCommand {
+    /// Input audio files
+    #[arg(value_name = "INPUTS", required = true)]
+    inputs: Vec<PathBuf>,
+
+    /// Directory to write outputs to (same file names as the inputs)
+    #[arg(short = 'o', long = "output-dir", value_name = "DIR", required_unless_present_any = ["in_place", "output_template"])]
+    output_dir: Option<PathBuf>,
+
+    /// Replace each input with its normalized audio instead of writing to a directory
+    #[arg(long = "in-place", conflicts_with = "output_dir")]
+    in_place: bool,
+
+    /// Output path built from each input, e.g. `{stem}_norm-{target}LUFS.{ext}` (see README for placeholders)
+    #[arg(long = "output-template", value_name = "TEMPLATE", conflicts_with_all = ["output_dir", "in_place"])]
+    output_template: Option<OutputTemplate>,
+
+    /// Skip the inputs an interrupted run of this batch already completed
+    #[arg(long)]
+    resume: bool,
+
+    #[command(flatten)]
+    normalize: NormalizeArgs,
+
+    #[command(flatten)]
+    decode: DecodeArgs,
+}
+
+#[derive(Args)]
+struct JobCommand {
+    /// Name of the `[jobs.NAME]` table
+    #[arg(value_name = "NAME")]
+    name: String,
+
+    /// Skip the inputs an interrupted run of this job already completed
+    #[arg(long)]
+    resume: bool,
+
+    #[command(flatten)]
+    normalize: NormalizeArgs,
+
+    #[command(flatten)]
+    decode: DecodeArgs,
+}
+
+#[derive(Args)]
+struct CheckCommand {
+    /// Input audio files
+    #[arg(value_name = "INPUTS", required = true)]
+    inputs: Vec<PathBuf>,
+
+    /// Delivery spec to check against
+    #[arg(long, value_enum)]
+    spec: Spec,
+
+    /// Print the results as JSON
+    #[arg(long)]
+    json: bool,
+
+    #[command(flatten)]
+    decode: DecodeArgs,
+}
+
+/// Normalization targets and fades
+#[derive(Args)]
+struct NormalizeArgs {
+    /// Target peak level in dB
+    #[arg(short = 'm', long = "max-peak", default_value = "-12.0", env = "AUDIO_NORMALIZER_MAX_PEAK")]
+    max_peak: f64,
+
+    /// Target LUFS level for loudness normalization
+    #[arg(short = 'l', long = "lufs", env = "AUDIO_NORMALIZER_LUFS")]
+    lufs: Option<f64>,
+
+    /// Delivery preset setting the LUFS target, true-peak ceiling and limiter together (see --list-presets)
+    #[arg(long, value_name = "NAME", env = "AUDIO_NORMALIZER_PRESET")]
+    preset: Option<String>,
+
+    /// True-peak ceiling in dBTP for LUFS normalization; overrides the preset's
+    #[arg(long = "true-peak", value_name = "DBTP", env = "AUDIO_NORMALIZER_TRUE_PEAK")]
+    true_peak: Option<f64>,
+
+    /// Fade in duration in seconds
+    #[arg(long = "fade-in", default_value = "0.0", env = "AUDIO_NORMALIZER_FADE_IN")]
+    fade_in: f64,
+
+    /// Fade out duration in seconds
+    #[arg(long = "fade-out", default_value = "0.0", env = "AUDIO_NORMALIZER_FADE_OUT")]
+    fade_out: f64,
+
+    /// Fade curve type (linear, exponential, logarithmic)
+    #[arg(long = "fade-curve", default_value = "linear", env = "AUDIO_NORMALIZER_FADE_CURVE")]
+    fade_curve: FadeCurve,
+
+    /// Force clipping if necessary to reach target LUFS (default: auto-adjust to prevent clipping)
+    #[arg(long = "force-clip", env = "AUDIO_NORMALIZER_FORCE_CLIP", value_parser = FalseyValueParser::new())]
+    force_clip: bool,
+
+    /// Processing stages in order, e.g. `trim,highpass:80,normalize,limiter:-1,fade`
+    /// (default: normalize,fade)
+    #[arg(long, value_name = "STAGES", value_delimiter = ',', env = "AUDIO_NORMALIZER_CHAIN")]
+    chain: Vec<StageSpec>,
+
+    /// Output format (e.g. wav); defaults to the OUTPUT extension, or wav when OUTPUT is `-`
+    #[arg(long = "output-format", value_name = "FORMAT")]
+    output_format: Option<String>,
+
+    /// Write headerless PCM in this sample format instead of a container
+    #[arg(long = "raw-output", value_enum, value_name = "FORMAT", conflicts_with = "output_format")]
+    raw_output: Option<RawSampleFormat>,
+
+    /// What to do when an output file already exists
+    #[arg(long = "on-collision", value_enum, value_name = "POLICY", default_value = "fail")]
+    on_collision: CollisionPolicy,
+
+    /// Replace output files that already exist (same as `--on-collision overwrite`)
+    #[arg(long, conflicts_with = "on_collision")]
+    overwrite: bool,
+
+    /// Measure and show the planned gain for each output without writing anything
+    #[arg(long = "dry-run")]
+    dry_run: bool,
+
+    /// Print the dry-run plan as JSON, one object per line and output
+    #[arg(long, requires = "dry_run")]
+    json: bool,
+
+    /// Decode each written output and fail if its level misses the target by more than the tolerance
+    #[arg(long, conflicts_with = "dry_run")]
+    verify: bool,
+
+    /// Largest accepted difference from the target for --verify, in dB (LU for LUFS targets)
+    #[arg(long = "verify-tolerance", value_name = "DB", default_value = "0.5", requires = "verify")]
+    verify_tolerance: f64,
+
+    /// Presets from configuration files, for `--preset`
+    #[arg(skip)]
+    custom_presets: BTreeMap<String, Preset>,
+}
+
+/// Track selection and handling of damaged or changing input
+#[derive(Args)]
+struct DecodeArgs {
+    /// What to do when a packet fails to decode
+    #[arg(long = "on-decode-error", value_enum, default_value = "silence", env = "AUDIO_NORMALIZER_ON_DECODE_ERROR")]
+    on_decode_error: DecodeErrorPolicy,
+
+    /// Strict decoding: verify checksums and fail on corruption or premature end of stream
+    #[arg(long, env = "AUDIO_NORMALIZER_STRICT", value_parser = FalseyValueParser::new())]
+    strict: bool,
+
+    /// What to do when the sample rate or channel count changes mid-stream (chained streams)
+    #[arg(long = "on-format-change", value_enum, default_value = "conform", env = "AUDIO_NORMALIZER_ON_FORMAT_CHANGE")]
+    on_format_change: FormatChangePolicy,
+
+    /// Audio track to process, by index (see the `tracks` command) or language code
+    #[arg(long, value_name = "INDEX|LANGUAGE")]
+    track: Option<TrackSelector>,
+
+    /// Input format (e.g. wav, mp3, flac) when INPUT is `-` or has no recognisable header
+    #[arg(long = "input-format", value_name = "FORMAT")]
+    input_format: Option<String>,
+
+    /// Read INPUT as headerless PCM in this sample format (needs --raw-channels and --raw-rate)
+    #[arg(long, value_enum, value_name = "FORMAT", requires_all = ["raw_channels", "raw_rate"], conflicts_with = "input_format")]
+    raw: Option<RawSampleFormat>,
+
+    /// Channel count of raw input
+    #[arg(long = "raw-channels", value_name = "N", requires = "raw")]
+    raw_channels: Option<usize>,
+
+    /// Sample rate of raw input
+    #[arg(long = "raw-rate", value_name = "HZ", requires = "raw")]
+    raw_rate: Option<usize>,
+}
+
+/// The pre-subcommand interface: `audio_normalizer [OPTIONS] INPUT [OUTPUT]`, deprecated
+#[derive(Args)]
+#[command(next_help_heading = "Legacy options (deprecated, use a subcommand)")]
+struct LegacyArgs {
+    /// Input audio file
+    #[arg(value_name = "INPUT")]
+    input: Option<PathBuf>,
+
+    /// Output audio file (optional - if not provided, will only analyze)
+    #[arg(value_name = "OUTPUT")]
+    output: Option<PathBuf>,
+
+    /// Only analyze peak level (use `peak`)
+    #[arg(long = "peak-only")]
+    peak_only: bool,
+
+    /// Only analyze LUFS level (use `lufs`)
+    #[arg(long = "lufs-only")]
+    lufs_only: bool,
+
+    #[command(flatten)]
+    normalize: NormalizeArgs,
+
+    #[command(flatten)]
+    decode: DecodeArgs,
+
+    /// Process every audio track into a separate output (use `normalize --all-tracks`)
+    #[arg(long = "all-tracks", requires = "output", conflicts_with = "track")]
+    all_tracks: bool,
+}
+
+impl DecodeArgs {
+    /// Fill in the options given neither on the command line nor in the environment from configuration defaults
+    fn apply_defaults(&mut self, defaults: &DecodeDefaults, matches: &ArgMatches) {
+        let unset = |id| !is_explicit(matches, id);
+        if let (true, Some(policy)) = (unset("on_decode_error"), defaults.on_decode_error) {
+            self.on_decode_error = policy;
+        }
+        if let (true, Some(strict)) = (unset("strict"), defaults.strict) {
+            self.strict = strict;
+        }
+        if let (true, Some(policy)) = (unset("on_format_change"), defaults.on_format_change) {
+            self.on_format_change = policy;
+        }
+    }
+
+    fn options(&self) -> DecodeOptions {
+        DecodeOptions {
+            track: self.track.clone(),
+            format: self.input_format.clone(),
+            on_error: self.on_decode_error,
+            on_format_change: self.on_format_change,
+            strict: self.strict,
+            raw: self.raw.map(|sample_format| RawFormat {
+                sample_format,
+                channels: self.raw_channels.unwrap_or_default(),
+                sample_rate: self.raw_rate.unwrap_or_default(),
+            }),
+        }
+    }
+}
+
+impl NormalizeArgs {
+    /// Whether stdout carries audio (OUTPUT is `-`) or a JSON plan rather than status messages
+    fn stdout_is_data(&self, output: &Path) -> bool {
+        if self.dry_run { self.json } else { is_stdio(output) }
+    }
+
+    /// Fill in the options given neither on the command line nor in the environment from configuration
+    /// defaults. The target (`-m`, `-l`, `--preset`) is taken as a whole from the strongest source.
+    fn apply_defaults(&mut self, defaults: &NormalizeDefaults, presets: &BTreeMap<String, Preset>, matches: &ArgMatches) -> Result<()> {
+        let source = |id| matches.value_source(id).filter(|s| *s != ValueSource::DefaultValue);
+        let target_source = source("max_peak").max(source("lufs")).max(source("preset"));
+        if target_source.is_none() {
+            self.max_peak = defaults.max_peak.unwrap_or(self.max_peak);
+            self.lufs = defaults.lufs;
+            self.preset = defaults.preset.clone();
+        } else {
+            if source("max_peak") == target_source && self.preset.is_some() && source("preset") == target_source {
+                return Err(Error::Usage("--preset cannot be combined with --max-peak".to_string()).into());
+            }
+            if source("lufs") < target_source {
+                self.lufs = None;
+            }
+            if source("preset") < target_source {
+                self.preset = None;
+            }
+        }
+
+        let unset = |id| !is_explicit(matches, id);
+        if unset("true_peak") {
+            self.true_peak = defaults.true_peak;
+        }
+        if let (true, Some(fade_in)) = (unset("fade_in"), defaults.fade_in) {
+            self.fade_in = fade_in;
+        }
+        if let (true, Some(fade_out)) = (unset("fade_out"), defaults.fade_out) {
+            self.fade_out = fade_out;
+        }
+        if let (true, Some(fade_curve)) = (unset("fade_curve"), defaults.fade_curve) {
+            self.fade_curve = fade_curve;
+        }
+        if let (true, Some(force_clip)) = (unset("force_clip"), defaults.force_clip) {
+            self.force_clip = force_clip;
+        }
+        if let (true, Some(chain)) = (unset("chain"), &defaults.chain) {
+            self.chain = chain.clone();
+        }
+        if unset("output_format") && self.raw_output.is_none() {
+            self.output_format = defaults.output_format.clone();
+        }
+        if let (true, false, Some(on_collision)) = (unset("on_collision"), self.overwrite, defaults.on_collision) {
+            self.on_collision = on_collision;
+        }
+        if let (true, false, Some(verify)) = (unset("verify"), self.dry_run, defaults.verify) {
+            self.verify = verify;
+        }
+        if let (true, Some(tolerance)) = (unset("verify_tolerance"), defaults.verify_tolerance) {
+            self.verify_tolerance = tolerance;
+        }
+        self.custom_presets = presets.clone();
+        Ok(())
+    }
+
+    /// The target and processing options, with the preset (if any) applied and explicit flags overriding it
+    fn resolve(&self, decode: &DecodeArgs) -> Result<(Target, NormalizeOptions)> {
+        let mut options = self.options(decode);
+        let mut target = match self.lufs {
+            Some(target_lufs) => Target::Lufs(target_lufs),
+            None => Target::Peak(self.max_peak),
+        };
+        if let Some(name) = &self.preset {
+            let preset = Preset::find(name, &self.custom_presets)?;
+            debug!("Preset {}: {}", preset.name, preset);
+            let preset_target = preset.apply(&mut options);
+            target = self.lufs.map_or(preset_target, Target::Lufs);
+        }
+        if let Some(true_peak) = self.true_peak {
+            options.true_peak_ceiling = Some(true_peak);
+        }
+        if options.true_peak_ceiling.is_some() && !matches!(target, Target::Lufs(_)) {
+            return Err(Error::Usage("--true-peak requires a LUFS target (--lufs or --preset)".to_string()).into());
+        }
+        match target {
+            Target::Lufs(target_lufs) => debug!("Target LUFS level: {:.2} LUFS", target_lufs),
+            Target::Peak(max_peak) => debug!("Target peak level: {:.2} dB", max_peak),
+        }
+        Ok((target, options))
+    }
+
+    fn options(&self, decode: &DecodeArgs) -> NormalizeOptions {
+        NormalizeOptions {
+            fade_in: self.fade_in,
+            fade_out: self.fade_out,
+            fade_curve: self.fade_curve,
+            force_clip: self.force_clip,
+            decode: decode.options(),
+            chain: self.chain.clone(),
+            output_format: self.output_format.clone(),
+            raw_output: self.raw_output,
+            on_collision: if self.overwrite { CollisionPolicy::Overwrite } else { self.on_collision },
+            in_place: false,
+            dry_run: self.dry_run,
+            verify: self.verify.then_some(self.verify_tolerance),
+            true_peak_ceiling: None,
+            claims: None,
+        }
+    }
+}
+
+/// Whether `id` was given on the command line or in the environment rather than left at its default
+fn is_explicit(matches: &ArgMatches, id: &str) -> bool {
+    matches!(matches.value_source(id), Some(ValueSource::CommandLine | ValueSource::EnvVariable))
+}
+
+/// Log to stderr and, with `--log-file`, to a rotating file through a non-blocking writer.
+///
+/// The returned guard flushes the file when dropped, so it must live until the program ends.
+fn setup_logging(cli: &Cli) -> Result<Option<WorkerGuard>> {
+    let level = if cli.verbose {
+        LevelFilter::DEBUG
+    } else if cli.quiet {
+        LevelFilter::ERROR
+    } else {
+        LevelFilter::INFO // Change back to INFO so we can see processing messages
+    };
+
+    let mut layers = vec![log_layer(cli.log_format, || ProgressAwareStderr, true).with_filter(level).boxed()];
+    if cli.progress_mode() != ProgressMode::Off {
+        let progress = Targets::new().with_target(PROGRESS_TARGET, LevelFilter::TRACE);
+        layers.push(ProgressLayer::new(update_progress).with_filter(progress).boxed());
+    }
+    let mut guard = None;
+    if let Some(path) = &cli.log_file {
+        let writer = log_file_writer(path, cli.log_rotation, cli.log_max_size * 1024 * 1024, cli.log_keep)
+            .map_err(|e| Error::output(path, e))?;
+        let (writer, worker) = tracing_appender::non_blocking(writer);
+        let file_level = if cli.verbose { LevelFilter::DEBUG } else { LevelFilter::INFO };
+        layers.push(log_layer(cli.log_format, writer, false).with_filter(file_level).boxed());
+        guard = Some(worker);
+    }
+
+    tracing_subscriber::registry().with(layers).init();
+    Ok(guard)
+}
+
+fn log_layer<W>(format: LogFormat, writer: W, ansi: bool) -> Box<dyn Layer<Registry> + Send + Sync>
+where
+    W: for<'a> MakeWriter<'a> + Send + Sync + 'static,
+{
+    match format {
+        LogFormat::Text => fmt::layer()
+            .with_writer(writer)
+            .with_ansi(ansi)
+            .with_target(false)
+            .with_level(true)
+            .compact()
+            .boxed(),
+        LogFormat::Json => fmt::layer()
+            .json()
+            .with_writer(writer)
+            .with_target(false)
+            .flatten_event(true)
+            .with_current_span(true)
+            .with_span_list(false)
+            .boxed(),
+    }
+}
+
+fn main() -> ExitCode {
+    let matches = Cli::command().get_matches();
+    let cli = Cli::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    
+    let _log_guard = match setup_logging(&cli) {
+        Ok(guard) => guard,
+        Err(e) => {
+            eprintln!("Error: {:?}", e);
+            return ExitCode::from(exit_code(&e));
+        }
+    };
+
+    debug!("Audio Normalizer v2.0.0");
+    handle_interrupts();
+
+    match run(cli, &matches) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {:?}", e);
+            ExitCode::from(exit_code(&e))
+        }
+    }
+}
+
+/// Stop cleanly on the first SIGINT or SIGTERM; on a second, delete partial outputs and exit at once
+fn handle_interrupts() {
+    let result = ctrlc::set_handler(|| {
+        if !audio_normalizer::interrupt() {
+            suspend_progress(|| eprintln!("Interrupted: stopping once the writes under way are finished (interrupt again to abort now)"));
+            return;
+        }
+        remove_temp_files();
+        suspend_progress(|| eprintln!("Aborted"));
+        std::process::exit(Error::Interrupted.exit_code().into());
+    });
+    if let Err(e) = result {
+        warn!("cannot handle interrupts: {}", e);
+    }
+}
+
+/// Exit code for a failure: the code of the first typed error in the chain, or 1
+fn exit_code(e: &anyhow::Error) -> u8 {
+    e.chain()
+        .find_map(|cause| cause.downcast_ref::<Error>())
+        .map_or(1, Error::exit_code)
+}
+
+fn run(cli: Cli, matches: &ArgMatches) -> Result<()> {
+    if cli.list_formats {
+        print_formats();
+        return Ok(());
+    }
+    let config = Config::load(cli.config.as_deref())?;
+    if cli.list_presets {
+        print_presets(&config);
+        return Ok(());
+    }
+
+    let files = match &cli.command {
+        Some(Command::Info { .. } | Command::Tracks { .. }) => None,
+        Some(Command::Batch(cmd)) => Some(cmd.inputs.len()),
+        Some(Command::Check(cmd)) => Some(cmd.inputs.len()),
+        Some(Command::Job(cmd)) => Some(config.jobs.get(&cmd.name).map_or(1, |job| job.inputs.len())),
+        _ => Some(1),
+    };
+    if let Some(files) = files {
+        start_progress(cli.progress_mode(), files);
+    }
+    let result = run_command(cli.command, cli.legacy, &config, matches);
+    finish_progress();
+    result
+}
+
+fn run_command(command: Option<Command>, legacy: LegacyArgs, config: &Config, matches: &ArgMatches) -> Result<()> {
+    // Flags of the subcommand, or of the legacy invocation
+    let matches = matches.subcommand().map_or(matches, |(_, matches)| matches);
+    match command {
+        Some(Command::Normalize(mut cmd)) => {
+            cmd.normalize.apply_defaults(&config.normalize, &config.presets, matches)?;
+            cmd.decode.apply_defaults(&config.decode, matches);
+            run_normalize_command(&cmd)
+        }
+        Some(Command::Analyze(mut cmd)) => {
+            cmd.decode.apply_defaults(&config.decode, matches);
+            run_analysis(&cmd.input, &cmd.decode, true, true)
+        }
+        Some(Command::Peak(mut cmd)) => {
+            cmd.decode.apply_defaults(&config.decode, matches);
+            run_analysis(&cmd.input, &cmd.decode, true, false)
+        }
+        Some(Command::Lufs(mut cmd)) => {
+            cmd.decode.apply_defaults(&config.decode, matches);
+            run_analysis(&cmd.input, &cmd.decode, false, true)
+        }
+        Some(Command::Info { input, json }) => print_info(&input, json),
+        Some(Command::Tracks { input }) => print_tracks(&input),
+        Some(Command::Batch(mut cmd)) => {
+            cmd.normalize.apply_defaults(&config.normalize, &config.presets, matches)?;
+            cmd.decode.apply_defaults(&config.decode, matches);
+            run_batch(&cmd)
+        }
+        Some(Command::Check(mut cmd)) => {
+            cmd.decode.apply_defaults(&config.decode, matches);
+            run_check(&cmd)
+        }
+        Some(Command::Job(cmd)) => run_job(cmd, config, matches),
+        None => {
+            let mut legacy = legacy;
+            legacy.normalize.apply_defaults(&config.normalize, &config.presets, matches)?;
+            legacy.decode.apply_defaults(&config.decode, matches);
+            run_legacy(&legacy)
+        }
+    }
+}
+
+/// Run a `[jobs.NAME]` batch, with its options over the configured defaults and flags over both
+fn run_job(mut cmd: JobCommand, config: &Config, matches: &ArgMatches) -> Result<()> {
+    let job = config.job(&cmd.name)?;
+    let outputs = [job.output_dir.is_some(), job.output_template.is_some(), job.in_place];
+    if outputs.iter().filter(|set| **set).count() != 1 {
+        return Err(Error::Usage(format!(
+            "job '{}' needs exactly one of output_dir, output_template or in_place",
+            cmd.name
+        ))
+        .into());
+    }
+    if job.inputs.is_empty() {
+        return Err(Error::Usage(format!("job '{}' has no inputs", cmd.name)).into());
+    }
+
+    let mut normalize = config.normalize.clone();
+    normalize.merge(job.normalize.clone());
+    let mut decode = config.decode.clone();
+    decode.merge(job.decode.clone());
+    cmd.normalize.apply_defaults(&normalize, &config.presets, matches)?;
+    cmd.decode.apply_defaults(&decode, matches);
+
+    info!("Running job {} ({} files)", cmd.name, job.inputs.len());
+    run_batch(&BatchCommand {
+        inputs: job.inputs.clone(),
+        output_dir: job.output_dir.clone(),
+        in_place: job.in_place,
+        output_template: job.output_template.clone(),
+        resume: cmd.resume,
+        normalize: cmd.normalize,
+        decode: cmd.decode,
+    })
+}
+
+/// Map the deprecated flag-based invocation onto the subcommands
+fn run_legacy(legacy: &LegacyArgs) -> Result<()> {
+    let Some(input) = &legacy.input else {
+        Cli::command().print_help()?;
+        return Err(Error::Usage("missing INPUT (or a subcommand)".to_string()).into());
+    };
+
+    if legacy.peak_only {
+        warn!("--peak-only is deprecated; use `audio_normalizer peak INPUT`");
+        return run_analysis(input, &legacy.decode, true, false);
+    }
+    if legacy.lufs_only {
+        warn!("--lufs-only is deprecated; use `audio_normalizer lufs INPUT`");
+        return run_analysis(input, &legacy.decode, false, true);
+    }
+
+    match &legacy.output {
+        Some(output) => {
+            warn!("`audio_normalizer INPUT OUTPUT` is deprecated; use `audio_normalizer normalize INPUT OUTPUT`");
+            run_normalize(input, output, &legacy.normalize, &legacy.decode, legacy.all_tracks)
+        }
+        None => {
+            warn!("`audio_normalizer INPUT` is deprecated; use `audio_normalizer peak INPUT`");
+            run_analysis(input, &legacy.decode, true, false)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    /// Serializes the tests that set `AUDIO_NORMALIZER_*` variables, which every parse reads
+    static ENV: Mutex<()> = Mutex::new(());
+
+    /// Parse `normalize in.wav out.wav ARGS` with `env` set, then apply `config` as `main` does
+    fn parse(args: &[&str], env: &[(&str, &str)], config: &str) -> Result<NormalizeCommand> {
+        let config: Config = toml::from_str(config)?;
+        let matches = {
+            let _env = ENV.lock().unwrap_or_else(|e| e.into_inner());
+            for (name, value) in env {
+                std::env::set_var(name, value);
+            }
+            let matches = Cli::command().try_get_matches_from(["audio_normalizer", "normalize", "in.wav", "out.wav"].iter().chain(args));
+            for (name, _) in env {
+                std::env::remove_var(name);
+            }
+            matches?
+        };
+        let Some(Command::Normalize(mut cmd)) = Cli::from_arg_matches(&matches)?.command else { unreachable!() };
+        let (_, matches) = matches.subcommand().unwrap();
+        cmd.normalize.apply_defaults(&config.normalize, &config.presets, matches)?;
+        cmd.decode.apply_defaults(&config.decode, matches);
+        Ok(cmd)
+    }
+
+    #[test]
+    fn flags_beat_the_environment_which_beats_the_config() {
+        let config = "[normalize]\nlufs = -18.0\nfade_in = 1.0";
+        let lufs = |args: &[&str], env: &[(&str, &str)]| parse(args, env, config).unwrap().normalize.lufs;
+        assert_eq!(lufs(&[], &[]), Some(-18.0));
+        assert_eq!(lufs(&[], &[("AUDIO_NORMALIZER_LUFS", "-20")]), Some(-20.0));
+        assert_eq!(lufs(&["-l", "-22"], &[("AUDIO_NORMALIZER_LUFS", "-20")]), Some(-22.0));
+
+        // A flag equal to its default still counts as given
+        assert_eq!(parse(&[], &[], config).unwrap().normalize.fade_in, 1.0);
+        assert_eq!(parse(&["--fade-in", "0"], &[], config).unwrap().normalize.fade_in, 0.0);
+    }
+
+    #[test]
+    fn the_target_comes_whole_from_the_strongest_source() {
+        let cmd = parse(&["-m", "-3"], &[], "[normalize]\npreset = \"spotify\"\nlufs = -15.0").unwrap();
+        assert_eq!((cmd.normalize.max_peak, cmd.normalize.lufs, cmd.normalize.preset), (-3.0, None, None));
+
+        let cmd = parse(&["-l", "-18"], &[("AUDIO_NORMALIZER_PRESET", "podcast")], "").unwrap();
+        assert_eq!((cmd.normalize.lufs, cmd.normalize.preset), (Some(-18.0), None));
+
+        // At the same level, --lufs overrides the preset's loudness but keeps its ceiling
+        let cmd = parse(&["--preset", "ebu-r128", "-l", "-20"], &[], "").unwrap();
+        let (target, options) = cmd.normalize.resolve(&cmd.decode).unwrap();
+        assert_eq!((target, options.true_peak_ceiling), (Target::Lufs(-20.0), Some(-1.0)));
+
+        assert!(parse(&["--preset", "ebu-r128", "-m", "-1"], &[], "").is_err());
+    }
+
+    #[test]
+    fn configured_presets_resolve() {
+        let cmd = parse(&[], &[], "[normalize]\npreset = \"house\"\n[presets.house]\nlufs = -19.0\ntrue_peak = -1.5").unwrap();
+        let (target, options) = cmd.normalize.resolve(&cmd.decode).unwrap();
+        assert_eq!((target, options.true_peak_ceiling), (Target::Lufs(-19.0), Some(-1.5)));
+    }
+
+    #[test]
+    fn flags_that_override_a_setting_win_over_it() {
+        let config = "[normalize]\non_collision = \"skip\"\nverify = true";
+        let cmd = parse(&["--overwrite"], &[], config).unwrap();
+        assert_eq!(cmd.normalize.options(&cmd.decode).on_collision, CollisionPolicy::Overwrite);
+        assert!(cmd.normalize.verify);
+        assert!(!parse(&["--dry-run"], &[], config).unwrap().normalize.verify);
+    }
+
+    #[test]
+    fn unknown_fade_curves_are_usage_errors() {
+        let config = "[normalize]\nfade_curve = \"log\"";
+        assert_eq!(parse(&[], &[], config).unwrap().normalize.fade_curve, FadeCurve::Logarithmic);
+        assert_eq!(parse(&["--fade-curve", "exp"], &[], config).unwrap().normalize.fade_curve, FadeCurve::Exponential);
+        for (args, env) in [(&["--fade-curve", "typo"][..], &[][..]), (&[], &[("AUDIO_NORMALIZER_FADE_CURVE", "typo")])] {
+            let error = parse(args, env, "").err().unwrap();
+            assert_eq!(error.downcast_ref::<clap::Error>().map(|e| e.exit_code()), Some(2), "{:#}", error);
+        }
+        assert!(parse(&[], &[], "[normalize]\nfade_curve = \"typo\"").is_err());
+    }
+
+    #[test]
+    fn boolean_environment_variables_can_turn_a_setting_off() {
+        let config = "[decode]\nstrict = true\non_decode_error = \"skip\"";
+        assert!(parse(&[], &[], config).unwrap().decode.strict);
+        assert!(!parse(&[], &[("AUDIO_NORMALIZER_STRICT", "false")], config).unwrap().decode.strict);
+        let cmd = parse(&[], &[("AUDIO_NORMALIZER_ON_DECODE_ERROR", "fail")], config).unwrap();
+        assert_eq!(cmd.decode.on_decode_error, DecodeErrorPolicy::Fail);
+    }
+
+    #[test]
+    fn log_sizes_that_overflow_are_usage_errors() {
+        let args = |size: &str| ["audio_normalizer", "--log-file", "a.log", "--log-max-size", size, "peak", "in.wav"].map(String::from);
+        let cli = Cli::try_parse_from(args("1048576")).unwrap();
+        assert_eq!(cli.log_max_size * 1024 * 1024, 1 << 40);
+        for size in ["0", "1048577", "18446744073709551615"] {
+            assert_eq!(Cli::try_parse_from(args(size)).err().map(|e| e.exit_code()), Some(2), "{}", size);
+        }
+    }
+
+    #[test]
+    fn interruptions_are_recognised_through_context() {
+        let error = anyhow::Error::from(Error::Interrupted).context("normalizing a.wav");
+        assert_eq!(exit_code(&error), 130);
+    }
+}
@@ -0,0 +1,234 @@
+//! Progress of the running command on stderr: a bar, periodic log lines or JSON events,
+//! fed by the library's decode progress events and by finished files
+
+use audio_normalizer::ProgressEvent;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::Path;
+use std::sync::atomic::AtomicBool;
+use std::sync::{mpsc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+use tracing::info;
+
+/// How progress is reported
+#[derive(Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub(crate) enum ProgressMode {
+    /// A bar when stderr is a terminal, otherwise log lines; nothing with --quiet
+    Auto,
+    /// A progress bar on stderr
+    Bar,
+    /// A log line every 10 seconds
+    Log,
+    /// One JSON event per line on stderr
+    Json,
+    /// No progress reporting
+    Off,
+}
+
+/// Set when stdout carries audio or JSON, so status messages must go to stderr instead
+pub(crate) static STDOUT_IS_DATA: AtomicBool = AtomicBool::new(false);
+
+/// `println!` for status messages, which move to stderr while stdout carries audio or JSON
+macro_rules! status {
+    ($($arg:tt)*) => {
+        $crate::progress::suspend_progress(|| {
+            if $crate::progress::STDOUT_IS_DATA.load(std::sync::atomic::Ordering::Relaxed) {
+                eprintln!($($arg)*)
+            } else {
+                println!($($arg)*)
+            }
+        })
+    };
+}
+
+/// Progress of the running command, fed by decode progress events and finished files
+struct Progress {
+    mode: ProgressMode,
+    started: Instant,
+    total: usize,
+    done: usize,
+    failed: usize,
+    /// Decoded fraction of each input being processed
+    active: BTreeMap<String, f64>,
+    /// Whether the bar is drawn on the current stderr line
+    bar_visible: bool,
+}
+
+/// A progress event for `--progress json`
+#[derive(Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+enum ProgressJson<'a> {
+    Progress { input: &'a str, frames: u64, total_frames: Option<u64>, fraction: Option<f64>, overall: f64 },
+    File { input: &'a str, status: &'static str, done: usize, failed: usize, total: usize },
+    Finished { done: usize, failed: usize, total: usize, elapsed_s: f64 },
+}
+
+static PROGRESS: Mutex<Option<Progress>> = Mutex::new(None);
+/// Stops and joins the thread redrawing the bar or logging progress
+static PROGRESS_TICKER: Mutex<Option<(mpsc::Sender<()>, JoinHandle<()>)>> = Mutex::new(None);
+
+impl Progress {
+    /// Overall fraction done, counting each file in progress by how much of it is decoded
+    fn overall(&self) -> f64 {
+        let finished = (self.done + self.failed) as f64;
+        ((finished + self.active.values().sum::<f64>()) / self.total.max(1) as f64).min(1.0)
+    }
+
+    fn summary(&self) -> String {
+        let overall = self.overall();
+        let what = match self.active.keys().next() {
+            Some(input) if self.total == 1 => input.clone(),
+            _ => format!("{}/{} files", self.done + self.failed, self.total),
+        };
+        let elapsed = self.started.elapsed().as_secs_f64();
+        let remaining = if overall > 0.01 {
+            format!(", about {} left", format_duration(elapsed / overall * (1.0 - overall)))
+        } else {
+            String::new()
+        };
+        format!("{:3.0}%  {}  {} elapsed{}", overall * 100.0, what, format_duration(elapsed), remaining)
+    }
+
+    fn draw_bar(&mut self) {
+        const WIDTH: usize = 30;
+        let filled = (self.overall() * WIDTH as f64).round() as usize;
+        let mut stderr = std::io::stderr().lock();
+        let _ = write!(stderr, "\r\x1b[2K[{}{}] {}", "#".repeat(filled), "-".repeat(WIDTH - filled), self.summary());
+        let _ = stderr.flush();
+        self.bar_visible = true;
+    }
+
+    fn clear_bar(&mut self) {
+        if self.bar_visible {
+            eprint!("\r\x1b[2K");
+            self.bar_visible = false;
+        }
+    }
+
+    fn emit_json(event: &ProgressJson) {
+        if let Ok(line) = serde_json::to_string(event) {
+            eprintln!("{}", line);
+        }
+    }
+}
+
+/// Start reporting progress over `total` input files
+pub(crate) fn start_progress(mode: ProgressMode, total: usize) {
+    if mode == ProgressMode::Off {
+        return;
+    }
+    *PROGRESS.lock().unwrap() = Some(Progress {
+        mode,
+        started: Instant::now(),
+        total,
+        done: 0,
+        failed: 0,
+        active: BTreeMap::new(),
+        bar_visible: false,
+    });
+
+    let interval = match mode {
+        ProgressMode::Bar => Duration::from_millis(100),
+        ProgressMode::Log => Duration::from_secs(10),
+        _ => return,
+    };
+    let (stop, stopped) = mpsc::channel::<()>();
+    let ticker = std::thread::spawn(move || {
+        while let Err(mpsc::RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+            let mut guard = PROGRESS.lock().unwrap();
+            let Some(progress) = guard.as_mut() else { break };
+            if progress.mode == ProgressMode::Bar {
+                progress.draw_bar();
+            } else {
+                let summary = progress.summary();
+                // Log without holding the lock, which the stderr log writer takes
+                drop(guard);
+                info!("progress: {}", summary);
+            }
+        }
+    });
+    *PROGRESS_TICKER.lock().unwrap() = Some((stop, ticker));
+}
+
+/// Record a decode progress event from the library
+pub(crate) fn update_progress(event: ProgressEvent) {
+    let mut guard = PROGRESS.lock().unwrap();
+    let Some(progress) = guard.as_mut() else { return };
+    progress.active.insert(event.input.clone(), event.fraction().unwrap_or(0.0));
+    if progress.mode == ProgressMode::Json {
+        Progress::emit_json(&ProgressJson::Progress {
+            input: &event.input,
+            frames: event.frames,
+            total_frames: event.total_frames,
+            fraction: event.fraction(),
+            overall: progress.overall(),
+        });
+    }
+}
+
+/// Count one input of a batch as done or failed
+pub(crate) fn finish_file(input: &Path, ok: bool) {
+    let mut guard = PROGRESS.lock().unwrap();
+    let Some(progress) = guard.as_mut() else { return };
+    let input = input.display().to_string();
+    progress.active.remove(&input);
+    if ok { progress.done += 1 } else { progress.failed += 1 }
+    if progress.mode == ProgressMode::Json {
+        Progress::emit_json(&ProgressJson::File {
+            input: &input,
+            status: if ok { "done" } else { "failed" },
+            done: progress.done,
+            failed: progress.failed,
+            total: progress.total,
+        });
+    }
+}
+
+/// Stop reporting progress and remove the bar
+pub(crate) fn finish_progress() {
+    if let Some((stop, ticker)) = PROGRESS_TICKER.lock().unwrap().take() {
+        drop(stop);
+        let _ = ticker.join();
+    }
+    let Some(mut progress) = PROGRESS.lock().unwrap().take() else { return };
+    progress.clear_bar();
+    if progress.mode == ProgressMode::Json {
+        Progress::emit_json(&ProgressJson::Finished {
+            done: progress.done,
+            failed: progress.failed,
+            total: progress.total,
+            elapsed_s: progress.started.elapsed().as_secs_f64(),
+        });
+    }
+}
+
+/// Run `print` with the progress bar cleared, so output never lands on the bar's line
+pub(crate) fn suspend_progress<R>(print: impl FnOnce() -> R) -> R {
+    let mut guard = PROGRESS.lock().unwrap();
+    if let Some(progress) = guard.as_mut() {
+        progress.clear_bar();
+    }
+    print()
+}
+
+/// Stderr for log lines, clearing the progress bar before each one
+pub(crate) struct ProgressAwareStderr;
+
+impl Write for ProgressAwareStderr {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        suspend_progress(|| std::io::stderr().write(buf))
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        std::io::stderr().flush()
+    }
+}
+
+/// `H:MM:SS`, or `M:SS` under an hour
+fn format_duration(seconds: f64) -> String {
+    let seconds = seconds.max(0.0).round() as u64;
+    let (h, m, s) = (seconds / 3600, seconds / 60 % 60, seconds % 60);
+    if h > 0 { format!("{}:{:02}:{:02}", h, m, s) } else { format!("{}:{:02}", m, s) }
+}
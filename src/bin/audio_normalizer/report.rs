@@ -0,0 +1,241 @@
+//! What the commands print: reports, plans, file information and the supported formats
+
+use anyhow::Result;
+use audio_normalizer::{
+    AudioData, Collision, ClippingAnalysis, ComplianceReport, Config, Error, FormatRegistry, MultiFormatProcessor,
+    NormalizeReport, OutputReport, Preset, Spec, Target, Verification,
+};
+use serde::Serialize;
+use std::path::Path;
+
+/// Output of `check --json`
+#[derive(Serialize)]
+pub(crate) struct CheckSummary<'a> {
+    pub(crate) spec: Spec,
+    pub(crate) passed: bool,
+    pub(crate) files: Vec<CheckedFile<'a>>,
+}
+
+#[derive(Serialize)]
+#[serde(untagged)]
+pub(crate) enum CheckedFile<'a> {
+    Report(ComplianceReport),
+    Failed { path: &'a Path, error: String },
+}
+
+pub(crate) fn print_compliance(report: &ComplianceReport) {
+    let verdict = |passed| if passed { "PASS" } else { "FAIL" };
+    println!("{}: {} ({})", report.path.display(), verdict(report.passed()), report.spec);
+    println!("  {:<20} {:<22} {:<16} RESULT", "CRITERION", "REQUIRED", "MEASURED");
+    for result in &report.results {
+        let metric = result.criterion.metric;
+        println!("  {:<20} {:<22} {:<16} {}",
+                 metric.label(),
+                 result.criterion.to_string(),
+                 format!("{:.2} {}", result.measured, metric.unit()),
+                 verdict(result.passed));
+    }
+}
+
+/// One output of a dry run, as printed by `--dry-run --json`
+#[derive(Serialize)]
+struct Plan<'a> {
+    input: &'a Path,
+    output: &'a Path,
+    mode: &'static str,
+    skipped: bool,
+    collision: Option<Collision>,
+    measured: Option<f64>,
+    requested_target: f64,
+    applied_target: Option<f64>,
+    requested_gain_db: Option<f64>,
+    applied_gain_db: Option<f64>,
+    safety_adjusted: Option<bool>,
+    clipping: Option<ClippingAnalysis>,
+}
+
+/// Show what a dry run would write, as text or as one JSON object per line
+pub(crate) fn print_plan(input: &Path, report: &OutputReport, target: Target, json: bool) -> audio_normalizer::Result<()> {
+    let mode = target.mode();
+    let unit = match target {
+        Target::Lufs(_) => "LUFS",
+        Target::Peak(_) => "dBFS",
+    };
+    let normalized = report.normalize.as_ref();
+
+    if json {
+        let plan = Plan {
+            input,
+            output: &report.path,
+            mode,
+            skipped: report.skipped(),
+            collision: report.collision,
+            measured: normalized.map(|n| n.measured),
+            requested_target: target.level(),
+            applied_target: normalized.map(|n| n.applied_target),
+            requested_gain_db: normalized.map(|n| n.requested_gain_db()),
+            applied_gain_db: normalized.map(|n| n.gain_db),
+            safety_adjusted: normalized.map(|n| n.safety_adjusted()),
+            clipping: normalized.and_then(|n| n.clipping),
+        };
+        let json = serde_json::to_string(&plan).map_err(|e| Error::output(Path::new("-"), e))?;
+        println!("{}", json);
+        return Ok(());
+    }
+
+    let Some(normalized) = normalized else {
+        println!("Dry run: {} -> {} (exists, would be skipped)", input.display(), report.path.display());
+        return Ok(());
+    };
+    println!("Dry run: {} -> {}", input.display(), report.path.display());
+    match report.collision {
+        Some(Collision::Exists) => println!("  collision: output exists, the file would fail (see --on-collision)"),
+        Some(Collision::Overwrite) => println!("  collision: output exists and would be overwritten"),
+        Some(Collision::Claimed) => println!("  collision: another input writes the same output, the file would fail (see --on-collision)"),
+        None => {}
+    }
+    println!("  measured: {:.2} {}, target: {:.2} {}", normalized.measured, unit, normalized.requested_target, unit);
+    println!("  requested gain: {:.2} dB, applied gain: {:.2} dB", normalized.requested_gain_db(), normalized.gain_db);
+    if normalized.safety_adjusted() {
+        println!("  safety adjustment: target lowered to {:.2} {} to avoid clipping", normalized.applied_target, unit);
+    } else if normalized.clipping.is_some_and(|c| c.would_clip) {
+        println!("  warning: output will clip (--force-clip)");
+    }
+    Ok(())
+}
+
+pub(crate) fn print_verification(verification: &Verification, target: Target) {
+    let unit = match target {
+        Target::Lufs(_) => "LUFS",
+        Target::Peak(_) => "dB",
+    };
+    status!("Verified: intended {:.2} {}, achieved {:.2} {} (tolerance: {:.2} dB)",
+            verification.intended, unit, verification.achieved, unit, verification.tolerance);
+    status!("  integrated: {:.2} LUFS, true peak: {:.2} dBTP", verification.integrated_lufs, verification.true_peak_db);
+}
+
+pub(crate) fn print_lufs_report(report: &NormalizeReport) {
+    if report.safety_adjusted() {
+        status!("LUFS normalization completed with safety adjustment:");
+        status!("  requested: {:.2} LUFS -> actual: {:.2} LUFS (gain: {:.2} dB)",
+                 report.requested_target, report.applied_target, report.gain_db);
+    } else {
+        status!("LUFS normalization completed: {:.2} LUFS (gain: {:.2} dB)",
+                 report.applied_target, report.gain_db);
+    }
+}
+
+pub(crate) fn print_damaged_regions(audio_data: &AudioData) {
+    if audio_data.damaged_regions.is_empty() {
+        return;
+    }
+
+    println!("Damaged regions (filled with silence): {}", audio_data.damaged_regions.len());
+    for region in &audio_data.damaged_regions {
+        println!("  at {:.3}s: {:.3}s ({} frames)",
+                 region.start_seconds(audio_data.sample_rate),
+                 region.duration_seconds(audio_data.sample_rate),
+                 region.frames);
+    }
+}
+
+pub(crate) fn print_tracks(input: &Path) -> Result<()> {
+    let tracks = MultiFormatProcessor::list_tracks(input)?;
+    if tracks.is_empty() {
+        println!("No audio tracks found");
+        return Ok(());
+    }
+
+    println!("{:<6} {:<6} {:<12} {:<10} {:<9} {:<10} DURATION", "INDEX", "ID", "CODEC", "LANGUAGE", "CHANNELS", "RATE");
+    for track in tracks {
+        let or_dash = |v: Option<String>| v.unwrap_or_else(|| "-".to_string());
+        println!("{:<6} {:<6} {:<12} {:<10} {:<9} {:<10} {}",
+                 track.index,
+                 track.id,
+                 track.codec,
+                 or_dash(track.language),
+                 or_dash(track.channels.map(|c| c.to_string())),
+                 or_dash(track.sample_rate.map(|r| format!("{} Hz", r))),
+                 or_dash(track.duration_seconds.map(|d| format!("{:.2}s", d))));
+    }
+    Ok(())
+}
+
+pub(crate) fn print_info(input: &Path, json: bool) -> Result<()> {
+    let file_info = MultiFormatProcessor::file_info(input)?;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&file_info)?);
+        return Ok(());
+    }
+
+    let or_dash = |v: Option<String>| v.unwrap_or_else(|| "-".to_string());
+    println!("File:       {}", file_info.path);
+    println!("Container:  {}", or_dash(file_info.container));
+    println!("Size:       {} bytes", file_info.file_size);
+    println!("Bitrate:    {}", or_dash(file_info.bitrate.map(|b| format!("{:.1} kbps", b as f64 / 1000.0))));
+
+    for track in &file_info.tracks {
+        println!();
+        println!("Track {} (id {})", track.index, track.id);
+        println!("  Codec:          {}", track.codec);
+        println!("  Language:       {}", or_dash(track.language.clone()));
+        println!("  Sample format:  {}", or_dash(track.sample_format.clone()));
+        println!("  Bit depth:      {}", or_dash(track.bits_per_sample.map(|b| b.to_string())));
+        println!("  Sample rate:    {}", or_dash(track.sample_rate.map(|r| format!("{} Hz", r))));
+        println!("  Channels:       {}", or_dash(track.channels.map(|c| c.to_string())));
+        println!("  Channel layout: {}", or_dash(track.channel_layout.clone()));
+        println!("  Duration:       {}", or_dash(track.n_frames.map(|n| match track.duration_seconds {
+            Some(d) => format!("{} frames ({:.3}s)", n, d),
+            None => format!("{} frames", n),
+        })));
+        println!("  Encoder delay:  {}", or_dash(track.gapless.map(|g| format!("{} frames", g.delay))));
+        println!("  Padding:        {}", or_dash(track.gapless.map(|g| format!("{} frames", g.padding))));
+    }
+
+    if !file_info.tags.is_empty() {
+        println!();
+        println!("Tags");
+        for tag in &file_info.tags {
+            println!("  {}: {}", tag.key, tag.value);
+        }
+    }
+    Ok(())
+}
+
+pub(crate) fn print_formats() {
+    let registry = FormatRegistry::global();
+    let extensions = |exts: &[&str]| exts.iter().map(|e| format!(".{}", e)).collect::<Vec<_>>().join(" ");
+
+    println!("Reading (also detected from header bytes):");
+    for decoder in registry.decoders() {
+        println!("  {:<10} {}", decoder.name(), extensions(decoder.extensions()));
+    }
+
+    println!();
+    println!("Writing (unknown extensions are written as {}):", registry.encoders().next().map_or("-", |e| e.name()));
+    for encoder in registry.encoders().filter(|e| e.supported()) {
+        println!("  {:<10} {:<12} {}", encoder.name(), extensions(encoder.extensions()), encoder.description());
+    }
+
+    let unsupported: Vec<_> = registry.encoders().filter(|e| !e.supported()).map(|e| e.name()).collect();
+    if !unsupported.is_empty() {
+        println!();
+        println!("Not supported for writing, written as WAV (.wav) instead: {}", unsupported.join(", "));
+    }
+}
+
+pub(crate) fn print_presets(config: &Config) {
+    let custom = &config.presets;
+    println!("Built-in presets:");
+    for preset in Preset::builtins() {
+        let overridden = if custom.contains_key(&preset.name) { " (overridden)" } else { "" };
+        println!("  {:<16} {}{}", preset.name, preset, overridden);
+    }
+    if !custom.is_empty() {
+        println!();
+        println!("Custom presets:");
+        for preset in custom.values() {
+            println!("  {:<16} {}", preset.name, preset);
+        }
+    }
+}
@@ -0,0 +1,255 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+use serde::Serialize;
+use crate::error::Result;
+use crate::multi_format_processor::{DecodeOptions, LoudnessStats, MultiFormatProcessor};
+
+/// Delivery specifications that `check` can verify a file against
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum Spec {
+    /// EBU R128 broadcast: -23 LUFS ±0.5 LU, true peak ≤ -1 dBTP
+    EbuR128,
+    /// ATSC A/85 US broadcast: -24 LKFS ±2 dB, true peak ≤ -2 dBTP
+    AtscA85,
+    /// Spotify: -14 LUFS ±1 LU, true peak ≤ -1 dBTP
+    Spotify,
+    /// Apple Music / Sound Check: -16 LUFS ±1 LU, true peak ≤ -1 dBTP
+    Apple,
+    /// YouTube: -14 LUFS ±1 LU, true peak ≤ -1 dBTP
+    Youtube,
+    /// ACX audiobooks: RMS -23 to -18 dBFS, sample peak ≤ -3 dBFS, noise floor ≤ -60 dBFS
+    Acx,
+    /// Netflix: -27 LKFS ±2 dB, true peak ≤ -2 dBTP, loudness range 4 to 18 LU
+    Netflix,
+}
+
+impl Spec {
+    pub fn name(self) -> &'static str {
+        match self {
+            Spec::EbuR128 => "ebu-r128",
+            Spec::AtscA85 => "atsc-a85",
+            Spec::Spotify => "spotify",
+            Spec::Apple => "apple",
+            Spec::Youtube => "youtube",
+            Spec::Acx => "acx",
+            Spec::Netflix => "netflix",
+        }
+    }
+
+    /// The requirements of this spec
+    pub fn criteria(self) -> Vec<Criterion> {
+        use Metric::*;
+        match self {
+            Spec::EbuR128 => vec![Criterion::around(IntegratedLoudness, -23.0, 0.5), Criterion::at_most(TruePeak, -1.0)],
+            Spec::AtscA85 => vec![Criterion::around(IntegratedLoudness, -24.0, 2.0), Criterion::at_most(TruePeak, -2.0)],
+            Spec::Spotify | Spec::Youtube => {
+                vec![Criterion::around(IntegratedLoudness, -14.0, 1.0), Criterion::at_most(TruePeak, -1.0)]
+            }
+            Spec::Apple => vec![Criterion::around(IntegratedLoudness, -16.0, 1.0), Criterion::at_most(TruePeak, -1.0)],
+            Spec::Acx => vec![
+                Criterion::between(Rms, -23.0, -18.0),
+                Criterion::at_most(SamplePeak, -3.0),
+                Criterion::at_most(NoiseFloor, -60.0),
+            ],
+            // Netflix gates on dialogue; without dialogue detection the programme loudness stands in for it
+            Spec::Netflix => vec![
+                Criterion::around(IntegratedLoudness, -27.0, 2.0),
+                Criterion::at_most(TruePeak, -2.0),
+                Criterion::between(LoudnessRange, 4.0, 18.0),
+            ],
+        }
+    }
+}
+
+impl fmt::Display for Spec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// A measured quantity a spec can constrain
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Metric {
+    IntegratedLoudness,
+    TruePeak,
+    SamplePeak,
+    LoudnessRange,
+    Rms,
+    NoiseFloor,
+}
+
+impl Metric {
+    pub fn label(self) -> &'static str {
+        match self {
+            Metric::IntegratedLoudness => "Integrated loudness",
+            Metric::TruePeak => "True peak",
+            Metric::SamplePeak => "Sample peak",
+            Metric::LoudnessRange => "Loudness range",
+            Metric::Rms => "RMS level",
+            Metric::NoiseFloor => "Noise floor",
+        }
+    }
+
+    pub fn unit(self) -> &'static str {
+        match self {
+            Metric::IntegratedLoudness => "LUFS",
+            Metric::TruePeak => "dBTP",
+            Metric::SamplePeak | Metric::Rms | Metric::NoiseFloor => "dBFS",
+            Metric::LoudnessRange => "LU",
+        }
+    }
+
+    pub fn value(self, stats: &LoudnessStats) -> f64 {
+        match self {
+            Metric::IntegratedLoudness => stats.integrated_lufs,
+            Metric::TruePeak => stats.true_peak_db,
+            Metric::SamplePeak => stats.sample_peak_db,
+            Metric::LoudnessRange => stats.loudness_range,
+            Metric::Rms => stats.rms_db,
+            Metric::NoiseFloor => stats.noise_floor_db,
+        }
+    }
+}
+
+/// An accepted range for one metric; either bound may be open
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Criterion {
+    pub metric: Metric,
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+}
+
+impl Criterion {
+    fn around(metric: Metric, target: f64, tolerance: f64) -> Self {
+        Criterion { metric, min: Some(target - tolerance), max: Some(target + tolerance) }
+    }
+
+    fn at_most(metric: Metric, max: f64) -> Self {
+        Criterion { metric, min: None, max: Some(max) }
+    }
+
+    fn between(metric: Metric, min: f64, max: f64) -> Self {
+        Criterion { metric, min: Some(min), max: Some(max) }
+    }
+
+    /// Whether `value` lies within the range; NaN never does, and negative infinity
+    /// (digital silence) only passes an upper bound
+    pub fn accepts(&self, value: f64) -> bool {
+        !value.is_nan() && self.min.is_none_or(|min| value >= min) && self.max.is_none_or(|max| value <= max)
+    }
+}
+
+impl fmt::Display for Criterion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let unit = self.metric.unit();
+        match (self.min, self.max) {
+            (Some(min), Some(max)) => write!(f, "{:.1} to {:.1} {}", min, max, unit),
+            (Some(min), None) => write!(f, ">= {:.1} {}", min, unit),
+            (None, Some(max)) => write!(f, "<= {:.1} {}", max, unit),
+            (None, None) => write!(f, "any"),
+        }
+    }
+}
+
+/// One criterion of a spec checked against a file
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct CriterionResult {
+    #[serde(flatten)]
+    pub criterion: Criterion,
+    pub measured: f64,
+    pub passed: bool,
+}
+
+/// Result of checking a file against a delivery spec
+#[derive(Debug, Clone, Serialize)]
+pub struct ComplianceReport {
+    pub path: PathBuf,
+    pub spec: Spec,
+    pub stats: LoudnessStats,
+    pub results: Vec<CriterionResult>,
+}
+
+impl ComplianceReport {
+    pub fn passed(&self) -> bool {
+        self.results.iter().all(|r| r.passed)
+    }
+}
+
+/// Measure a file and check every criterion of `spec`
+pub fn check(input: &Path, spec: Spec, options: &DecodeOptions) -> Result<ComplianceReport> {
+    let stats = MultiFormatProcessor::get_loudness_stats(input, options)?;
+    let results = spec
+        .criteria()
+        .into_iter()
+        .map(|criterion| {
+            let measured = criterion.metric.value(&stats);
+            CriterionResult { criterion, measured, passed: criterion.accepts(measured) }
+        })
+        .collect();
+    Ok(ComplianceReport { path: input.to_path_buf(), spec, stats, results })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::temp_path;
+
+    /// Two seconds of 997 Hz at `amplitude`, as 32-bit float so that levels are exact
+    fn sine_file(name: &str, amplitude: f32) -> PathBuf {
+        let path = temp_path(&format!("{}.wav", name));
+        let spec = hound::WavSpec { channels: 1, sample_rate: 48000, bits_per_sample: 32, sample_format: hound::SampleFormat::Float };
+        let mut writer = hound::WavWriter::create(&path, spec).unwrap();
+        for i in 0..96000 {
+            writer.write_sample(amplitude * (2.0 * std::f32::consts::PI * 997.0 * i as f32 / 48000.0).sin()).unwrap();
+        }
+        writer.finalize().unwrap();
+        path
+    }
+
+    #[test]
+    fn criteria_bounds_are_inclusive() {
+        let around = Criterion::around(Metric::IntegratedLoudness, -23.0, 0.5);
+        assert!(around.accepts(-23.5) && around.accepts(-22.5) && around.accepts(-23.0));
+        assert!(!around.accepts(-23.51) && !around.accepts(-22.49));
+        let at_most = Criterion::at_most(Metric::TruePeak, -1.0);
+        assert!(at_most.accepts(-1.0) && !at_most.accepts(-0.99));
+    }
+
+    #[test]
+    fn silence_and_nan_verdicts() {
+        assert!(Criterion::at_most(Metric::NoiseFloor, -60.0).accepts(f64::NEG_INFINITY));
+        assert!(!Criterion::between(Metric::Rms, -23.0, -18.0).accepts(f64::NEG_INFINITY));
+        assert!(!Criterion::at_most(Metric::TruePeak, -1.0).accepts(f64::NAN));
+    }
+
+    #[test]
+    fn criteria_display_their_range() {
+        assert_eq!(Criterion::around(Metric::IntegratedLoudness, -23.0, 0.5).to_string(), "-23.5 to -22.5 LUFS");
+        assert_eq!(Criterion::at_most(Metric::TruePeak, -1.0).to_string(), "<= -1.0 dBTP");
+    }
+
+    #[test]
+    fn every_spec_has_criteria() {
+        for spec in <Spec as clap::ValueEnum>::value_variants() {
+            assert!(!spec.criteria().is_empty(), "{}", spec);
+        }
+    }
+
+    #[test]
+    fn check_reports_a_verdict_per_criterion() {
+        // A 997 Hz sine at -20 dBFS peak measures about -23 LUFS and -20 dBTP
+        let path = sine_file("compliance", 0.1);
+        let ebu = check(&path, Spec::EbuR128, &DecodeOptions::default()).unwrap();
+        assert!(ebu.passed(), "{:?}", ebu.results);
+        assert_eq!(ebu.results.len(), Spec::EbuR128.criteria().len());
+
+        let spotify = check(&path, Spec::Spotify, &DecodeOptions::default()).unwrap();
+        assert!(!spotify.passed());
+        let verdicts: Vec<_> = spotify.results.iter().map(|r| (r.criterion.metric, r.passed)).collect();
+        assert_eq!(verdicts, [(Metric::IntegratedLoudness, false), (Metric::TruePeak, true)]);
+        assert!((spotify.results[0].measured + 23.0).abs() < 0.2, "{}", spotify.results[0].measured);
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,274 @@
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use serde::{Deserialize, Deserializer};
+use crate::error::{Error, Result};
+use crate::fade::FadeCurve;
+use crate::multi_format_processor::{DecodeErrorPolicy, FormatChangePolicy};
+use crate::normalizer::CollisionPolicy;
+use crate::preset::Preset;
+use crate::processor::StageSpec;
+use crate::template::OutputTemplate;
+
+/// Name of the per-project configuration file, looked up in the working directory
+pub const PROJECT_FILE: &str = "audio_normalizer.toml";
+
+/// Settings read from configuration files.
+///
+/// Every setting is optional; a value left unset falls through to the next source
+/// (see `Config::load` for the order).
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// Default normalization options, the `[normalize]` table
+    #[serde(default)]
+    pub normalize: NormalizeDefaults,
+    /// Default decoding options, the `[decode]` table
+    #[serde(default)]
+    pub decode: DecodeDefaults,
+    /// Custom presets by name, as `[presets.NAME]` tables
+    #[serde(default)]
+    pub presets: BTreeMap<String, Preset>,
+    /// Batch jobs by name, as `[jobs.NAME]` tables
+    #[serde(default)]
+    pub jobs: BTreeMap<String, Job>,
+}
+
+/// Defaults for the options of `normalize` and `batch`, named after their long flags
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NormalizeDefaults {
+    pub max_peak: Option<f64>,
+    pub lufs: Option<f64>,
+    pub preset: Option<String>,
+    pub true_peak: Option<f64>,
+    pub fade_in: Option<f64>,
+    pub fade_out: Option<f64>,
+    #[serde(default, deserialize_with = "parse")]
+    pub fade_curve: Option<FadeCurve>,
+    pub force_clip: Option<bool>,
+    /// Comma-separated stages, as for `--chain`
+    #[serde(default, deserialize_with = "parse_chain")]
+    pub chain: Option<Vec<StageSpec>>,
+    pub output_format: Option<String>,
+    pub on_collision: Option<CollisionPolicy>,
+    pub verify: Option<bool>,
+    pub verify_tolerance: Option<f64>,
+}
+
+/// Defaults for the decoding options
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DecodeDefaults {
+    pub on_decode_error: Option<DecodeErrorPolicy>,
+    pub strict: Option<bool>,
+    pub on_format_change: Option<FormatChangePolicy>,
+}
+
+/// A named batch run: its inputs, where the outputs go and the options it overrides
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Job {
+    pub inputs: Vec<PathBuf>,
+    pub output_dir: Option<PathBuf>,
+    #[serde(default, deserialize_with = "parse")]
+    pub output_template: Option<OutputTemplate>,
+    #[serde(default)]
+    pub in_place: bool,
+    /// Options for this job, over the `[normalize]` defaults
+    #[serde(default)]
+    pub normalize: NormalizeDefaults,
+    /// Decoding options for this job, over the `[decode]` defaults
+    #[serde(default)]
+    pub decode: DecodeDefaults,
+}
+
+impl Config {
+    /// `<config dir>/audio_normalizer/config.toml`, e.g. `~/.config/audio_normalizer/config.toml` on Linux
+    pub fn user_file() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join("audio_normalizer").join("config.toml"))
+    }
+
+    /// Load `file` alone if given (it must exist), otherwise the user file and then the project file
+    /// in the working directory, with project settings winning. Missing default files are skipped.
+    pub fn load(file: Option<&Path>) -> Result<Config> {
+        if let Some(path) = file {
+            return Config::read(path)?.ok_or_else(|| Error::Config {
+                path: path.to_path_buf(),
+                reason: "no such file".to_string(),
+            });
+        }
+        let mut config = Config::default();
+        for path in Config::user_file().into_iter().chain([PathBuf::from(PROJECT_FILE)]) {
+            if let Some(file) = Config::read(&path)? {
+                config.merge(file);
+            }
+        }
+        Ok(config)
+    }
+
+    /// Parse one file, or `None` if it does not exist
+    pub fn read(path: &Path) -> Result<Option<Config>> {
+        let text = match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(Error::Config { path: path.to_path_buf(), reason: e.to_string() }),
+        };
+        let mut config: Config = toml::from_str(&text)
+            .map_err(|e| Error::Config { path: path.to_path_buf(), reason: e.message().to_string() })?;
+        for (name, preset) in &mut config.presets {
+            preset.name = name.clone();
+        }
+        Ok(Some(config))
+    }
+
+    /// Look up a batch job by name
+    pub fn job(&self, name: &str) -> Result<&Job> {
+        self.jobs.get(name).ok_or_else(|| {
+            let names: Vec<&str> = self.jobs.keys().map(String::as_str).collect();
+            let available = if names.is_empty() { "none defined".to_string() } else { names.join(", ") };
+            Error::Usage(format!("unknown job '{}' (available: {})", name, available))
+        })
+    }
+
+    /// Overlay `other` on this configuration; its settings, presets and jobs win
+    fn merge(&mut self, other: Config) {
+        self.normalize.merge(other.normalize);
+        self.decode.merge(other.decode);
+        self.presets.extend(other.presets);
+        self.jobs.extend(other.jobs);
+    }
+}
+
+impl NormalizeDefaults {
+    /// Overlay `other` on these defaults; values it sets win
+    pub fn merge(&mut self, other: NormalizeDefaults) {
+        // A target in `other` replaces the whole target, so a project `lufs` is not overridden by a user `preset`
+        if other.max_peak.is_some() || other.lufs.is_some() || other.preset.is_some() {
+            self.max_peak = other.max_peak;
+            self.lufs = other.lufs;
+            self.preset = other.preset;
+        }
+        self.true_peak = other.true_peak.or(self.true_peak);
+        self.fade_in = other.fade_in.or(self.fade_in);
+        self.fade_out = other.fade_out.or(self.fade_out);
+        self.fade_curve = other.fade_curve.or(self.fade_curve);
+        self.force_clip = other.force_clip.or(self.force_clip);
+        self.chain = other.chain.or(self.chain.take());
+        self.output_format = other.output_format.or(self.output_format.take());
+        self.on_collision = other.on_collision.or(self.on_collision);
+        self.verify = other.verify.or(self.verify);
+        self.verify_tolerance = other.verify_tolerance.or(self.verify_tolerance);
+    }
+}
+
+impl DecodeDefaults {
+    /// Overlay `other` on these defaults; values it sets win
+    pub fn merge(&mut self, other: DecodeDefaults) {
+        self.on_decode_error = other.on_decode_error.or(self.on_decode_error);
+        self.strict = other.strict.or(self.strict);
+        self.on_format_change = other.on_format_change.or(self.on_format_change);
+    }
+}
+
+/// Deserialize a string through `FromStr`
+fn parse<'de, D, T>(deserializer: D) -> std::result::Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr,
+    T::Err: Display,
+{
+    let text = String::deserialize(deserializer)?;
+    text.parse().map(Some).map_err(serde::de::Error::custom)
+}
+
+/// Deserialize a comma-separated list of stages
+fn parse_chain<'de, D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Option<Vec<StageSpec>>, D::Error> {
+    let text = String::deserialize(deserializer)?;
+    text.split(',')
+        .map(|stage| stage.trim().parse())
+        .collect::<std::result::Result<_, _>>()
+        .map(Some)
+        .map_err(serde::de::Error::custom)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::temp_path;
+
+    fn config(text: &str) -> Config {
+        toml::from_str(text).unwrap()
+    }
+
+    #[test]
+    fn later_files_win_setting_by_setting() {
+        let mut user = config("[normalize]\nlufs = -16.0\nfade_in = 1.0\nverify = true\n[decode]\nstrict = true");
+        user.merge(config("[normalize]\nfade_in = 2.0\n[decode]\non_decode_error = \"skip\""));
+        assert_eq!((user.normalize.lufs, user.normalize.fade_in, user.normalize.verify), (Some(-16.0), Some(2.0), Some(true)));
+        assert_eq!((user.decode.strict, user.decode.on_decode_error), (Some(true), Some(DecodeErrorPolicy::Skip)));
+    }
+
+    #[test]
+    fn a_target_replaces_the_whole_target() {
+        let mut defaults = config("[normalize]\npreset = \"spotify\"\nmax_peak = -3.0").normalize;
+        defaults.merge(config("[normalize]\nlufs = -18.0").normalize);
+        assert_eq!((defaults.max_peak, defaults.lufs, defaults.preset.as_deref()), (None, Some(-18.0), None));
+
+        // Settings other than the target leave it alone
+        defaults.merge(config("[normalize]\ntrue_peak = -2.0").normalize);
+        assert_eq!((defaults.lufs, defaults.true_peak), (Some(-18.0), Some(-2.0)));
+    }
+
+    #[test]
+    fn presets_and_jobs_merge_by_name() {
+        let mut user = config("[presets.house]\nlufs = -18.0\n[presets.loud]\nlufs = -9.0\n[jobs.a]\ninputs = [\"a.wav\"]");
+        user.merge(config("[presets.house]\nlufs = -20.0\n[jobs.b]\ninputs = [\"b.wav\"]"));
+        assert_eq!((user.presets["house"].lufs, user.presets["loud"].lufs), (-20.0, -9.0));
+        assert_eq!(user.jobs.keys().collect::<Vec<_>>(), ["a", "b"]);
+    }
+
+    #[test]
+    fn unknown_keys_are_rejected() {
+        for text in ["[normalize]\nlufs_target = -16.0", "[decode]\nstrict = true\nloud = 1", "verbose = true", "[jobs.a]\ninputs = []\nmode = 1"] {
+            assert!(toml::from_str::<Config>(text).is_err(), "{}", text);
+        }
+    }
+
+    #[test]
+    fn chains_parse_like_the_flag() {
+        let defaults = config("[normalize]\nchain = \"highpass:80, normalize ,limiter:-1\"").normalize;
+        assert_eq!(
+            defaults.chain.unwrap(),
+            [StageSpec::HighPass { cutoff_hz: 80.0 }, StageSpec::Normalize, StageSpec::Limiter { ceiling_db: -1.0 }]
+        );
+        let error = toml::from_str::<Config>("[normalize]\nchain = \"normalize,reverb\"").unwrap_err();
+        assert!(error.message().contains("reverb"), "{}", error.message());
+    }
+
+    #[test]
+    fn jobs_are_found_by_name() {
+        let config = config("[jobs.podcast]\ninputs = [\"ep1.wav\"]\noutput_template = \"out/{stem}.wav\"\n[jobs.music]\ninputs = []");
+        let job = config.job("podcast").unwrap();
+        assert_eq!((job.inputs.as_slice(), job.in_place), ([PathBuf::from("ep1.wav")].as_slice(), false));
+        assert!(job.output_template.is_some());
+        let Err(Error::Usage(message)) = config.job("nope") else { panic!("expected a usage error") };
+        assert!(message.contains("music, podcast"), "{}", message);
+        let Err(Error::Usage(message)) = Config::default().job("nope") else { panic!("expected a usage error") };
+        assert!(message.contains("none defined"), "{}", message);
+    }
+
+    #[test]
+    fn files_are_read_with_preset_names() {
+        let path = temp_path("config.toml");
+        std::fs::write(&path, "[presets.house]\nlufs = -18.0").unwrap();
+        assert_eq!(Config::read(&path).unwrap().unwrap().presets["house"].name, "house");
+        std::fs::write(&path, "[normalize]\nlufs = \"loud\"").unwrap();
+        assert!(matches!(Config::read(&path), Err(Error::Config { .. })));
+        let _ = std::fs::remove_file(&path);
+
+        assert!(Config::read(&path).unwrap().is_none());
+        assert!(matches!(Config::load(Some(&path)), Err(Error::Config { .. })));
+    }
+}
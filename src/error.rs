@@ -0,0 +1,97 @@
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+use crate::multi_format_processor::DecodeFailure;
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Errors surfaced by decoding, processing and writing audio.
+///
+/// Each variant maps to a stable process exit code (see `exit_code` and the README),
+/// so scripts can tell bad input apart from bad output or an unreachable target.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("invalid arguments: {0}")]
+    Usage(String),
+    #[error("invalid configuration in {}: {reason}", path.display())]
+    Config { path: PathBuf, reason: String },
+    #[error("cannot read {}", path.display())]
+    Input {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+    #[error("unsupported input {}: {reason}", path.display())]
+    Unsupported { path: PathBuf, reason: String },
+    #[error("no audio track matching '{0}'")]
+    TrackNotFound(String),
+    #[error(transparent)]
+    Decode(#[from] DecodeFailure),
+    #[error("cannot write {}: {reason}", path.display())]
+    Output { path: PathBuf, reason: String },
+    #[error("cannot reach loudness target of {target:.2} LUFS: {reason}")]
+    LoudnessTarget { target: f64, reason: String },
+    #[error("{failed} of {total} files do not meet {spec}")]
+    NonCompliant { failed: usize, total: usize, spec: String },
+    #[error("verification of {} failed: {reason}", path.display())]
+    Verification { path: PathBuf, reason: String },
+    #[error("{failed} of {total} files failed")]
+    Batch { failed: usize, total: usize },
+    #[error("processing failed: {0}")]
+    Processing(String),
+    #[error("interrupted")]
+    Interrupted,
+}
+
+impl Error {
+    /// Process exit code for this error
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            Error::Processing(_) => 1,
+            Error::Usage(_) | Error::Config { .. } | Error::TrackNotFound(_) => 2,
+            Error::Input { .. } => 3,
+            Error::Unsupported { .. } => 4,
+            Error::Decode(_) => 5,
+            Error::Output { .. } => 6,
+            Error::LoudnessTarget { .. } => 7,
+            Error::Batch { .. } => 8,
+            Error::Verification { .. } => 9,
+            Error::NonCompliant { .. } => 10,
+            // 128 + SIGINT, as shells report a process killed by Ctrl-C
+            Error::Interrupted => 130,
+        }
+    }
+
+    pub fn unsupported(path: &Path, reason: impl Display) -> Self {
+        Error::Unsupported { path: path.to_path_buf(), reason: reason.to_string() }
+    }
+
+    pub fn output(path: &Path, reason: impl Display) -> Self {
+        Error::Output { path: path.to_path_buf(), reason: reason.to_string() }
+    }
+
+    /// Classify an error from opening or reading an input with hound
+    pub fn from_hound_read(path: &Path, e: hound::Error) -> Self {
+        match e {
+            hound::Error::IoError(source) => Error::Input { path: path.to_path_buf(), source },
+            e => Error::unsupported(path, e),
+        }
+    }
+}
+
+impl From<ebur128::Error> for Error {
+    fn from(e: ebur128::Error) -> Self {
+        Error::Processing(format!("loudness measurement: {}", e))
+    }
+}
+
+impl From<rubato::ResamplerConstructionError> for Error {
+    fn from(e: rubato::ResamplerConstructionError) -> Self {
+        Error::Processing(format!("resampler: {}", e))
+    }
+}
+
+impl From<rubato::ResampleError> for Error {
+    fn from(e: rubato::ResampleError) -> Self {
+        Error::Processing(format!("resampler: {}", e))
+    }
+}
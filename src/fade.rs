@@ -1,19 +1,39 @@
-#[derive(Clone, Copy, Debug)]
+use crate::error::Result;
+use crate::multi_format_processor::AudioData;
+use crate::processor::Processor;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
 pub enum FadeCurve {
+    #[default]
     Linear,
     Exponential,
     Logarithmic,
 }
 
-impl FadeCurve {
-    pub fn from_str(s: &str) -> Self {
-        match s.to_lowercase().as_str() {
-            "exponential" | "exp" => FadeCurve::Exponential,
-            "logarithmic" | "log" => FadeCurve::Logarithmic,
-            _ => FadeCurve::Linear,
+impl std::str::FromStr for FadeCurve {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "linear" | "lin" => Ok(FadeCurve::Linear),
+            "exponential" | "exp" => Ok(FadeCurve::Exponential),
+            "logarithmic" | "log" => Ok(FadeCurve::Logarithmic),
+            other => Err(format!("unknown fade curve '{}' (expected linear, exponential or logarithmic)", other)),
         }
     }
+}
+
+impl std::fmt::Display for FadeCurve {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            FadeCurve::Linear => "linear",
+            FadeCurve::Exponential => "exponential",
+            FadeCurve::Logarithmic => "logarithmic",
+        })
+    }
+}
 
+impl FadeCurve {
     pub fn in_gain(self, t: f32) -> f32 {
         match self {
             FadeCurve::Linear => t,
@@ -64,3 +84,48 @@ pub fn apply_fades(samples: &mut [f32], channels: usize, sample_rate: usize, fad
     }
 }
 
+
+/// Fade in and out as a chain stage
+#[derive(Debug, Clone)]
+pub struct Fade {
+    /// Fade in duration in seconds
+    pub fade_in: f64,
+    /// Fade out duration in seconds
+    pub fade_out: f64,
+    pub curve: FadeCurve,
+}
+
+impl Processor for Fade {
+    fn name(&self) -> &'static str {
+        "fade"
+    }
+
+    fn process(&mut self, audio_data: &mut AudioData) -> Result<()> {
+        apply_fades(&mut audio_data.samples, audio_data.channels, audio_data.sample_rate, self.fade_in, self.fade_out, self.curve);
+        Ok(())
+    }
+
+    fn is_transparent(&self) -> bool {
+        self.fade_in <= 0.0 && self.fade_out <= 0.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn curves_parse_by_name_or_abbreviation() {
+        for (name, curve) in [("linear", FadeCurve::Linear), ("EXP", FadeCurve::Exponential), (" log ", FadeCurve::Logarithmic)] {
+            assert_eq!(name.parse::<FadeCurve>(), Ok(curve));
+            assert_eq!(curve.to_string().parse::<FadeCurve>(), Ok(curve));
+        }
+    }
+
+    #[test]
+    fn unknown_curves_are_rejected() {
+        let error = "lineer".parse::<FadeCurve>().unwrap_err();
+        assert!(error.contains("'lineer'"), "{}", error);
+        assert!("".parse::<FadeCurve>().is_err());
+    }
+}
@@ -0,0 +1,27 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use crate::error::{Error, Result};
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Ask running work to stop, returning whether it had already been asked.
+///
+/// Decoders give up between packets and `normalize_to_template` before it starts writing,
+/// both with `Error::Interrupted`. A write that is already under way is finished, so an
+/// interrupt never leaves a partial output behind.
+pub fn interrupt() -> bool {
+    INTERRUPTED.swap(true, Ordering::SeqCst)
+}
+
+/// Whether `interrupt` has been called
+pub fn is_interrupted() -> bool {
+    INTERRUPTED.load(Ordering::Relaxed)
+}
+
+/// Fail with `Error::Interrupted` once an interrupt has been requested
+pub(crate) fn check() -> Result<()> {
+    if is_interrupted() {
+        Err(Error::Interrupted)
+    } else {
+        Ok(())
+    }
+}
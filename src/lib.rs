@@ -0,0 +1,60 @@
+//! Audio normalization library: decoding, peak and LUFS normalization, an effect chain and encoding.
+//!
+//! The `audio_normalizer` binary is a thin command-line wrapper around this crate.
+//! Nothing here prints to stdout; progress and warnings are reported through `tracing`.
+
+pub mod atomic;
+pub mod audio_processor;
+pub mod batch;
+pub mod compliance;
+pub mod config;
+pub mod error;
+pub mod fade;
+pub mod interrupt;
+pub mod logging;
+pub mod multi_format_processor;
+pub mod normalizer;
+pub mod preset;
+pub mod processor;
+pub mod progress;
+pub mod raw;
+pub mod registry;
+pub mod template;
+#[cfg(test)]
+mod test_util;
+
+use std::path::Path;
+
+pub use atomic::{remove_temp_files, write_atomically};
+pub use batch::{Batch, BatchOutput, BatchSummary, FileOutcome, ResumeState, RESUME_FILE_PREFIX};
+pub use compliance::{ComplianceReport, Criterion, CriterionResult, Metric, Spec};
+pub use config::Config;
+pub use error::{Error, Result};
+pub use fade::{apply_fades, Fade, FadeCurve};
+pub use interrupt::{interrupt, is_interrupted};
+pub use logging::{log_file_writer, LogRotation, SizeRotatingFile};
+pub use multi_format_processor::{
+    is_stdio, AudioData, DamagedRegion, DecodeErrorPolicy, DecodeFailure, DecodeOptions, FormatChangePolicy,
+    LoudnessStats, MultiFormatProcessor, TrackSelector,
+};
+pub use normalizer::{
+    analyze_clipping_risk, build_chain, normalize_lufs, normalize_lufs_buffer, normalize_peak, normalize_peak_buffer,
+    normalize_to_template, Claim, ClippingAnalysis, Collision, CollisionPolicy, Normalize, NormalizeOptions, NormalizeReport, OutputClaims, OutputReport,
+    Target, Verification,
+};
+pub use preset::Preset;
+pub use raw::{decode_raw, encode_raw, RawFormat, RawSampleFormat};
+pub use registry::{AudioDecoder, AudioEncoder, FormatRegistry};
+pub use processor::{Chain, ChainBuilder, Gain, HighPass, Limiter, Processor, StageSpec, Trim};
+pub use progress::{ProgressEvent, ProgressLayer, PROGRESS_TARGET};
+pub use template::{OutputTemplate, TemplateValues};
+
+/// Decode a file of any supported format into memory
+pub fn decode(input: &Path, options: &DecodeOptions) -> Result<AudioData> {
+    MultiFormatProcessor::decode_audio_with_options(input, options)
+}
+
+/// Encode audio to a file, choosing the format from its extension and keeping the source bit depth
+pub fn encode(output: &Path, audio_data: &AudioData) -> Result<()> {
+    MultiFormatProcessor::write_audio_data(output, audio_data, audio_data.bits_per_sample)
+}
@@ -0,0 +1,157 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// When a log file is rotated
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum LogRotation {
+    /// Start a new file each day, named `FILE.YYYY-MM-DD`
+    #[default]
+    Daily,
+    /// Start a new file each hour, named `FILE.YYYY-MM-DD-HH`
+    Hourly,
+    /// Rename `FILE` to `FILE.1` (and older ones to `FILE.2`, ...) once it reaches the size limit
+    Size,
+    /// Append to `FILE` forever
+    Never,
+}
+
+/// Open a log file writer with the given rotation, keeping at most `keep` rotated files.
+///
+/// Time-based rotation uses `tracing_appender`; `max_size` (in bytes) only applies to `Size`.
+/// The parent directory is created if needed.
+pub fn log_file_writer(path: &Path, rotation: LogRotation, max_size: u64, keep: usize) -> io::Result<Box<dyn Write + Send>> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    fs::create_dir_all(dir)?;
+    let Some(name) = path.file_name() else {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "the log file path has no file name"));
+    };
+
+    let timed = |rotation| {
+        tracing_appender::rolling::RollingFileAppender::builder()
+            .rotation(rotation)
+            .filename_prefix(name.to_string_lossy())
+            .max_log_files(keep.max(1))
+            .build(dir)
+            .map_err(io::Error::other)
+    };
+    Ok(match rotation {
+        LogRotation::Daily => Box::new(timed(tracing_appender::rolling::Rotation::DAILY)?),
+        LogRotation::Hourly => Box::new(timed(tracing_appender::rolling::Rotation::HOURLY)?),
+        LogRotation::Size => Box::new(SizeRotatingFile::open(path.to_path_buf(), max_size, keep)?),
+        LogRotation::Never => Box::new(OpenOptions::new().create(true).append(true).open(path)?),
+    })
+}
+
+/// A file that is renamed to `FILE.1` and started afresh once a write would take it past `max_size`.
+///
+/// Older files shift to `FILE.2`, `FILE.3`, ... and the oldest beyond `keep` is deleted.
+/// A single write larger than `max_size` still goes to one file, so log lines are never split.
+pub struct SizeRotatingFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    max_size: u64,
+    keep: usize,
+}
+
+impl SizeRotatingFile {
+    pub fn open(path: PathBuf, max_size: u64, keep: usize) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let size = file.metadata()?.len();
+        Ok(SizeRotatingFile { path, file, size, max_size, keep: keep.max(1) })
+    }
+
+    fn rotated(&self, n: usize) -> PathBuf {
+        let mut name = self.path.clone().into_os_string();
+        name.push(format!(".{}", n));
+        PathBuf::from(name)
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        let _ = fs::remove_file(self.rotated(self.keep));
+        for n in (1..self.keep).rev() {
+            let from = self.rotated(n);
+            if from.exists() {
+                fs::rename(&from, self.rotated(n + 1))?;
+            }
+        }
+        fs::rename(&self.path, self.rotated(1))?;
+        self.file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+}
+
+impl Write for SizeRotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.size > 0 && self.size + buf.len() as u64 > self.max_size {
+            self.rotate()?;
+        }
+        let written = self.file.write(buf)?;
+        self.size += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::temp_dir;
+
+    fn read(path: PathBuf) -> String {
+        fs::read_to_string(path).unwrap_or_default()
+    }
+
+    #[test]
+    fn rotates_only_once_a_write_would_pass_the_limit() {
+        let dir = temp_dir("log-threshold");
+        let mut log = SizeRotatingFile::open(dir.join("app.log"), 10, 3).unwrap();
+        log.write_all(b"123456").unwrap();
+        log.write_all(b"7890").unwrap();
+        assert!(!log.rotated(1).exists());
+        log.write_all(b"x").unwrap();
+        assert_eq!((read(log.rotated(1)), read(dir.join("app.log"))), ("1234567890".to_string(), "x".to_string()));
+
+        // A write larger than the limit is not split
+        log.write_all(b"a line longer than the limit").unwrap();
+        assert_eq!((read(log.rotated(1)), read(dir.join("app.log"))), ("x".to_string(), "a line longer than the limit".to_string()));
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn shifts_older_files_and_deletes_those_beyond_keep() {
+        let dir = temp_dir("log-keep");
+        let mut log = SizeRotatingFile::open(dir.join("app.log"), 4, 2).unwrap();
+        for line in ["aaaa", "bbbb", "cccc", "dddd"] {
+            log.write_all(line.as_bytes()).unwrap();
+        }
+        assert_eq!(read(dir.join("app.log")), "dddd");
+        assert_eq!((read(log.rotated(1)), read(log.rotated(2))), ("cccc".to_string(), "bbbb".to_string()));
+        assert!(!log.rotated(3).exists());
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn appends_to_the_file_left_by_a_previous_run() {
+        let dir = temp_dir("log-restart");
+        let path = dir.join("app.log");
+        SizeRotatingFile::open(path.clone(), 10, 3).unwrap().write_all(b"first ").unwrap();
+
+        // The size left by the previous run counts towards the limit
+        let mut log = SizeRotatingFile::open(path.clone(), 10, 3).unwrap();
+        log.write_all(b"run").unwrap();
+        assert_eq!(read(path.clone()), "first run");
+        log.write_all(b"!!").unwrap();
+        assert_eq!((read(log.rotated(1)), read(path)), ("first run".to_string(), "!!".to_string()));
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -1,12 +1,22 @@
-use anyhow::{anyhow, Result};
 use ebur128::EbuR128;
+use std::io::{Read, Write};
 use std::path::Path;
-use symphonia::core::audio::{AudioBufferRef, Signal};
-use symphonia::core::codecs::DecoderOptions;
-use symphonia::core::formats::FormatOptions;
-use symphonia::core::io::MediaSourceStream;
-use symphonia::core::meta::MetadataOptions;
-use symphonia::core::probe::Hint;
+use crate::error::{Error, Result};
+use crate::interrupt;
+use crate::progress::DecodeProgress;
+use crate::raw::RawFormat;
+use crate::registry::{read_header, FormatRegistry};
+use crate::audio_processor::{int_to_f32, remap_channels, resample};
+use symphonia::core::audio::{AudioBuffer, AudioBufferRef, Signal};
+use symphonia::core::sample::Sample;
+use symphonia::core::units::TimeBase;
+use symphonia::core::codecs::{DecoderOptions, CODEC_TYPE_NULL};
+use symphonia::core::errors::Error as SymphoniaError;
+use symphonia::core::formats::{FormatOptions, FormatReader, Track};
+use symphonia::core::io::{MediaSource, MediaSourceStream, ReadOnlySource};
+use serde::{Deserialize, Serialize};
+use symphonia::core::meta::{MetadataOptions, MetadataRevision, Tag};
+use symphonia::core::probe::{Hint, ProbeResult, ProbedMetadata};
 use tracing::warn;
 
 /// Audio format detection and conversion
@@ -15,347 +25,1193 @@ pub struct MultiFormatProcessor;
 impl MultiFormatProcessor {
     /// Get peak level from any supported audio format
     pub fn get_peak_level(input: &Path) -> Result<f64> {
-        let audio_data = Self::decode_audio_to_f32(input)?;
-        let mut peak: f32 = 0.0;
-        
-        for sample in &audio_data.samples {
-            let abs_sample = sample.abs();
-            if abs_sample > peak {
-                peak = abs_sample;
-            }
-        }
-        
-        if peak <= 0.0 {
-            return Ok(f64::NEG_INFINITY);
-        }
-        Ok(20.0 * (peak as f64).log10())
+        Ok(Self::decode_audio_to_f32(input)?.peak_db())
     }
     
     /// Get LUFS level from any supported audio format
     pub fn get_lufs_level(input: &Path) -> Result<f64> {
-        let audio_data = Self::decode_audio_to_f32(input)?;
-        
-        let mut meter = EbuR128::new(
-            audio_data.channels as u32,
-            audio_data.sample_rate as u32,
-            ebur128::Mode::I,
-        )?;
-        
-        meter.add_frames_f32(&audio_data.samples)?;
-        let lufs = meter.loudness_global()?;
-        Ok(lufs as f64)
+        Self::decode_audio_to_f32(input)?.integrated_lufs()
+    }
+
+    /// Get integrated loudness, true peak, loudness range and level statistics from any supported audio format
+    pub fn get_loudness_stats(input: &Path, options: &DecodeOptions) -> Result<LoudnessStats> {
+        Self::decode_audio_with_options(input, options)?.loudness_stats()
     }
     
     /// Decode any supported audio format to f32 samples
     pub fn decode_audio_to_f32(input: &Path) -> Result<AudioData> {
-        // First try WAV with hound (faster for WAV files)
-        if let Some(ext) = input.extension() {
-            if ext.to_string_lossy().to_lowercase() == "wav" {
-                return Self::decode_wav_with_hound(input);
-            }
+        Self::decode_audio_with_options(input, &DecodeOptions::default())
+    }
+
+    /// Decode any supported audio format to f32 samples, handling damaged input as configured.
+    /// Mid-stream format changes are always conformed to the initial spec.
+    pub fn decode_audio_with_options(input: &Path, options: &DecodeOptions) -> Result<AudioData> {
+        conform_segments(Self::decode_segments(input, options)?)
+    }
+
+    /// Decode into one `AudioData` per output, honoring `options.on_format_change`:
+    /// a single conformed stream, or one segment per run of constant sample rate and channel count
+    pub fn decode_audio_segments(input: &Path, options: &DecodeOptions) -> Result<Vec<AudioData>> {
+        let segments = Self::decode_segments(input, options)?;
+        match options.on_format_change {
+            FormatChangePolicy::Conform => Ok(vec![conform_segments(segments)?]),
+            FormatChangePolicy::Split => Ok(segments),
         }
-        
-        // Use Symphonia for other formats
-        Self::decode_with_symphonia(input)
+    }
+
+    fn decode_segments(input: &Path, options: &DecodeOptions) -> Result<Vec<AudioData>> {
+        FormatRegistry::global().decode(input, options)
     }
     
-    /// Decode WAV files using hound (faster)
-    fn decode_wav_with_hound(input: &Path) -> Result<AudioData> {
+    /// Decode WAV files using hound (faster).
+    ///
+    /// A data chunk shorter than its header claims is handled by the decode policy, like a
+    /// damaged stream: the missing end is filled with silence, dropped, or fails the decode.
+    pub(crate) fn decode_wav_with_hound(input: &Path, options: &DecodeOptions) -> Result<AudioData> {
+        // A WAV file only ever holds a single track
+        match options.track.as_ref() {
+            None | Some(TrackSelector::Index(0)) => {}
+            Some(selector) => return Err(Error::TrackNotFound(selector.to_string())),
+        }
+
         let mut reader = hound::WavReader::open(input)
-            .map_err(|e| anyhow!("Failed to open WAV file: {}", e))?;
+            .map_err(|e| Error::from_hound_read(input, e))?;
         
         let spec = reader.spec();
+        let expected = reader.len() as u64 / spec.channels as u64;
         let mut samples = Vec::new();
-        
-        match spec.sample_format {
-            hound::SampleFormat::Float => {
-                for sample in reader.samples::<f32>() {
-                    samples.push(sample?);
+        let mut damaged_regions = Vec::new();
+        let mut progress = DecodeProgress::new(input, Some(expected));
+        let channels = spec.channels.max(1) as usize;
+        let chunk = PROGRESS_CHUNK_FRAMES * channels;
+
+        let bits = spec.bits_per_sample;
+        let decoded: Box<dyn Iterator<Item = hound::Result<f32>>> = match spec.sample_format {
+            hound::SampleFormat::Float => Box::new(reader.samples::<f32>()),
+            hound::SampleFormat::Int => Box::new(reader.samples::<i32>().map(move |s| s.map(|s| int_to_f32(s, bits)))),
+        };
+        let mut truncated = None;
+        for sample in decoded {
+            match sample {
+                Ok(sample) => samples.push(sample),
+                // I/O errors mid-read mean the data chunk is shorter than its header claims
+                Err(hound::Error::IoError(e)) => {
+                    truncated = Some(e);
+                    break;
                 }
+                Err(e) => return Err(Error::unsupported(input, e)),
             }
-            hound::SampleFormat::Int => {
-                let max = (1i64 << (spec.bits_per_sample - 1)) as f32;
-                for sample in reader.samples::<i32>() {
-                    samples.push(sample? as f32 / max);
-                }
+            if samples.len() % chunk == 0 {
+                progress.advance(PROGRESS_CHUNK_FRAMES as u64);
+                interrupt::check()?;
             }
         }
+
+        if let Some(e) = truncated {
+            // Drop the incomplete last frame
+            samples.truncate(samples.len() / channels * channels);
+            let decoded = (samples.len() / channels) as u64;
+            let policy = options.policy();
+            if policy == DecodeErrorPolicy::Fail {
+                return Err(DecodeFailure::PrematureEof { decoded, expected }.into());
+            }
+            let seconds = frames_to_seconds(decoded, spec.sample_rate as usize);
+            let reason = format!("premature end of stream ({})", e);
+            recover(policy, &mut samples, &mut damaged_regions, channels, expected - decoded, seconds, reason)?;
+        }
+        progress.finish();
         
         Ok(AudioData {
             samples,
             channels: spec.channels as usize,
             sample_rate: spec.sample_rate as usize,
+            bits_per_sample: spec.bits_per_sample,
+            is_float: spec.sample_format == hound::SampleFormat::Float,
+            damaged_regions,
         })
     }
     
-    /// Decode using Symphonia (supports MP3, FLAC, OGG, etc.)
-    fn decode_with_symphonia(input: &Path) -> Result<AudioData> {
-        let file = std::fs::File::open(input)?;
-        let mss = MediaSourceStream::new(Box::new(file), Default::default());
+    /// Open and probe a file (or stdin for `-`) with Symphonia without decoding any audio
+    fn probe(input: &Path, format: Option<&str>) -> Result<ProbeResult> {
+        if is_stdio(input) {
+            return Self::probe_source(input, Box::new(ReadOnlySource::new(std::io::stdin())), format);
+        }
+
+        let file = std::fs::File::open(input)
+            .map_err(|source| Error::Input { path: input.to_path_buf(), source })?;
+        Self::probe_source(input, Box::new(file), format)
+    }
+
+    /// Probe a media source; `format` (a name or extension) overrides the input's extension as a hint
+    fn probe_source(input: &Path, source: Box<dyn MediaSource>, format: Option<&str>) -> Result<ProbeResult> {
+        let mss = MediaSourceStream::new(source, Default::default());
         
         let mut hint = Hint::new();
-        if let Some(extension) = input.extension() {
-            hint.with_extension(&extension.to_string_lossy());
+        match format {
+            Some(format) => { hint.with_extension(format); }
+            None => if let Some(extension) = input.extension() {
+                hint.with_extension(&extension.to_string_lossy());
+            }
         }
         
         let meta_opts: MetadataOptions = Default::default();
-        let fmt_opts: FormatOptions = Default::default();
+        // Let demuxers that understand encoder delay and padding (LAME/Xing, Ogg) trim their packets
+        let fmt_opts = FormatOptions { enable_gapless: true, ..Default::default() };
         
-        let probed = symphonia::default::get_probe()
+        symphonia::default::get_probe()
             .format(&hint, mss, &fmt_opts, &meta_opts)
-            .map_err(|e| anyhow!("Failed to probe format: {}", e))?;
-        
-        let mut format = probed.format;
-        let track = format
-            .tracks()
-            .iter()
-            .find(|t| t.codec_params.codec != symphonia::core::codecs::CODEC_TYPE_NULL)
-            .ok_or_else(|| anyhow!("No supported audio tracks found"))?;
+            .map_err(|e| match e {
+                SymphoniaError::IoError(source) => Error::Input { path: input.to_path_buf(), source },
+                e => Error::unsupported(input, e),
+            })
+    }
+
+    /// List the audio tracks of a file in the order used by `TrackSelector::Index`
+    pub fn list_tracks(input: &Path) -> Result<Vec<TrackInfo>> {
+        let ProbeResult { mut format, mut metadata } = Self::probe(input, None)?;
+        Ok(track_infos(&mut metadata, format.as_mut()))
+    }
+
+    /// Describe a file from its headers without decoding any audio
+    pub fn file_info(input: &Path) -> Result<FileInfo> {
+        let file_size = std::fs::metadata(input)
+            .map_err(|source| Error::Input { path: input.to_path_buf(), source })?
+            .len();
+        let container = ContainerFormat::detect(input)?;
+        let ProbeResult { mut format, mut metadata } = Self::probe(input, None)?;
+        let tracks = track_infos(&mut metadata, format.as_mut());
+
+        // Tags may live in a header read during probing (e.g. ID3v2) or in the container itself
+        let mut tags = Vec::new();
+        if let Some(revision) = metadata.get().as_ref().and_then(|m| m.current()) {
+            tags.extend(revision.tags().iter().map(TagInfo::from));
+        }
+        if let Some(revision) = format.metadata().current() {
+            tags.extend(revision.tags().iter().map(TagInfo::from));
+        }
+
+        // Average bitrate over the whole file, container overhead included
+        let bitrate = tracks
+            .first()
+            .and_then(|t| t.duration_seconds)
+            .filter(|&d| d > 0.0)
+            .map(|d| (file_size as f64 * 8.0 / d).round() as u64);
+
+        Ok(FileInfo {
+            path: input.display().to_string(),
+            container: container.map(|c| c.name().to_string()),
+            file_size,
+            bitrate,
+            tracks,
+            tags,
+        })
+    }
+    
+    /// Decode using Symphonia (supports MP3, FLAC, OGG, etc.)
+    ///
+    /// Returns one segment per run of constant sample rate and channel count, so chained
+    /// streams whose format changes mid-way are never interleaved with the wrong layout.
+    pub(crate) fn decode_with_symphonia(input: &Path, options: &DecodeOptions) -> Result<Vec<AudioData>> {
+        let probed = Self::probe(input, options.format.as_deref())?;
+        Self::decode_probed(input, probed, options)
+    }
+
+    /// Decode with Symphonia from a stream that cannot seek, such as stdin
+    pub(crate) fn decode_stream_with_symphonia(
+        input: &Path,
+        stream: Box<dyn Read + Send + Sync>,
+        options: &DecodeOptions,
+    ) -> Result<Vec<AudioData>> {
+        let source = Box::new(ReadOnlySource::new(stream));
+        let probed = Self::probe_source(input, source, options.format.as_deref())?;
+        Self::decode_probed(input, probed, options)
+    }
+
+    fn decode_probed(input: &Path, probed: ProbeResult, options: &DecodeOptions) -> Result<Vec<AudioData>> {
+        let ProbeResult { mut format, mut metadata } = probed;
+        let track = select_track(input, format.tracks(), options.track.as_ref())?.clone();
+        let gapless = gapless_info(&track, &mut metadata, format.as_mut());
         
         let track_id = track.id;
-        // Create decoder options with maximum tolerance for errors
-        let mut dec_opts = DecoderOptions::default();
-        // Disable all verification and error checking where possible
-        dec_opts.verify = false;
+        let time_base = track.codec_params.time_base;
+        let n_frames = track.codec_params.n_frames;
+        // Lossy codecs have no native bit depth; write them back as 16-bit
+        let bits_per_sample = track.codec_params.bits_per_sample.unwrap_or(16) as u16;
+        let is_float = matches!(
+            track.codec_params.sample_format,
+            Some(symphonia::core::sample::SampleFormat::F32 | symphonia::core::sample::SampleFormat::F64)
+        );
+        // Only verify checksums in strict mode; otherwise tolerate as much damage as possible
+        let dec_opts = DecoderOptions { verify: options.strict };
+        let policy = options.policy();
         
         let mut decoder = symphonia::default::get_codecs()
             .make(&track.codec_params, &dec_opts)
-            .map_err(|e| anyhow!("Failed to create decoder: {}", e))?;
+            .map_err(|e| Error::unsupported(input, e))?;
         
+        let mut segments = Vec::new();
         let mut samples = Vec::new();
-        let mut sample_rate = 0;
-        let mut channels = 0;
+        // Start from the container's parameters so damage before the first good packet can be filled
+        let mut sample_rate = track.codec_params.sample_rate.unwrap_or(0) as usize;
+        let mut channels = track.codec_params.channels.map(|c| c.count()).unwrap_or(0);
+        let mut have_spec = false;
+        let mut damaged_regions = Vec::new();
+        // Timestamp at which the next packet should start; a later one means packets were lost
+        let mut expected_ts: Option<u64> = None;
+        // Whether the demuxer already trimmed encoder delay and padding at the packet level
+        let mut packets_trimmed = false;
+        let mut progress = DecodeProgress::new(input, n_frames);
         
         // Decode all packets
         loop {
+            interrupt::check()?;
             let packet = match format.next_packet() {
                 Ok(packet) => packet,
-                Err(_) => break,
+                Err(SymphoniaError::IoError(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
+                Err(e) if policy == DecodeErrorPolicy::Fail => {
+                    return Err(DecodeFailure::Unreadable { reason: e.to_string() }.into());
+                }
+                Err(e) => {
+                    warn!("stopping at unreadable packet: {}", e);
+                    break;
+                }
             };
-            
+
             if packet.track_id() != track_id {
                 continue;
             }
+
+            let seconds = frames_to_seconds(ts_to_frames(packet.ts(), time_base, sample_rate), sample_rate);
+            if let Some(expected) = expected_ts.filter(|&ts| packet.ts() > ts) {
+                let missing = ts_to_frames(packet.ts() - expected, time_base, sample_rate);
+                let reason = format!("{} frames missing", missing);
+                recover(policy, &mut samples, &mut damaged_regions, channels, missing, seconds, reason)?;
+            }
+            expected_ts = Some(packet.ts() + packet.dur());
             
             match decoder.decode(&packet) {
                 Ok(decoded) => {
-                    if sample_rate == 0 {
-                        let spec = decoded.spec();
-                        sample_rate = spec.rate as usize;
-                        channels = spec.channels.count();
+                    let spec = decoded.spec();
+                    let (rate, count) = (spec.rate as usize, spec.channels.count());
+                    if have_spec && (rate != sample_rate || count != channels) {
+                        warn!("format change at {:.3}s: {} Hz/{} ch -> {} Hz/{} ch",
+                              seconds, sample_rate, channels, rate, count);
+                        segments.push(AudioData {
+                            samples: std::mem::take(&mut samples),
+                            channels,
+                            sample_rate,
+                            bits_per_sample,
+                            is_float,
+                            damaged_regions: std::mem::take(&mut damaged_regions),
+                        });
                     }
+                    sample_rate = rate;
+                    channels = count;
+                    have_spec = true;
                     
-                    // Convert to f32 and interleave channels
-                    let frame_count = decoded.frames();
-                    let channel_count = decoded.spec().channels.count();
-                    
-                    match decoded {
-                        AudioBufferRef::F32(buf) => {
-                            // Interleave samples: [L,R,L,R,...] instead of [L,L,L...,R,R,R...]
-                            for frame in 0..frame_count {
-                                for ch in 0..channel_count {
-                                    let channel_data = buf.chan(ch);
-                                    samples.push(channel_data[frame]);
-                                }
-                            }
-                        }
-                        AudioBufferRef::U8(buf) => {
-                            for frame in 0..frame_count {
-                                for ch in 0..channel_count {
-                                    let channel_data = buf.chan(ch);
-                                    let sample = channel_data[frame];
-                                    samples.push((sample as f32 - 128.0) / 127.0);
-                                }
-                            }
-                        }
-                        AudioBufferRef::U16(buf) => {
-                            for frame in 0..frame_count {
-                                for ch in 0..channel_count {
-                                    let channel_data = buf.chan(ch);
-                                    let sample = channel_data[frame];
-                                    samples.push((sample as f32 - 32768.0) / 32767.0);
-                                }
-                            }
-                        }
-                        AudioBufferRef::U24(buf) => {
-                            for frame in 0..frame_count {
-                                for ch in 0..channel_count {
-                                    let channel_data = buf.chan(ch);
-                                    let sample = channel_data[frame];
-                                    let val = sample.inner() as f32;
-                                    samples.push((val - 8388608.0) / 8388607.0);
-                                }
-                            }
-                        }
-                        AudioBufferRef::U32(buf) => {
-                            for frame in 0..frame_count {
-                                for ch in 0..channel_count {
-                                    let channel_data = buf.chan(ch);
-                                    let sample = channel_data[frame];
-                                    samples.push((sample as f32 - 2147483648.0) / 2147483647.0);
-                                }
-                            }
-                        }
-                        AudioBufferRef::S8(buf) => {
-                            for frame in 0..frame_count {
-                                for ch in 0..channel_count {
-                                    let channel_data = buf.chan(ch);
-                                    let sample = channel_data[frame];
-                                    samples.push(sample as f32 / 127.0);
-                                }
-                            }
-                        }
-                        AudioBufferRef::S16(buf) => {
-                            for frame in 0..frame_count {
-                                for ch in 0..channel_count {
-                                    let channel_data = buf.chan(ch);
-                                    let sample = channel_data[frame];
-                                    samples.push(sample as f32 / 32767.0);
-                                }
-                            }
-                        }
-                        AudioBufferRef::S24(buf) => {
-                            for frame in 0..frame_count {
-                                for ch in 0..channel_count {
-                                    let channel_data = buf.chan(ch);
-                                    let sample = channel_data[frame];
-                                    let val = sample.inner() as f32;
-                                    samples.push(val / 8388607.0);
-                                }
-                            }
-                        }
-                        AudioBufferRef::S32(buf) => {
-                            for frame in 0..frame_count {
-                                for ch in 0..channel_count {
-                                    let channel_data = buf.chan(ch);
-                                    let sample = channel_data[frame];
-                                    samples.push(sample as f32 / 2147483647.0);
-                                }
-                            }
-                        }
-                        AudioBufferRef::F64(buf) => {
-                            for frame in 0..frame_count {
-                                for ch in 0..channel_count {
-                                    let channel_data = buf.chan(ch);
-                                    let sample = channel_data[frame];
-                                    samples.push(sample as f32);
-                                }
-                            }
-                        }
+                    let start = samples.len();
+                    let frames = decoded.frames() as u64;
+                    // Convert to f32 and interleave channels: [L,R,L,R,...] instead of [L,L,L...,R,R,R...]
+                    append_interleaved(decoded, &mut samples);
+                    progress.advance(frames);
+
+                    // Apply the demuxer's delay/padding trim if the decoder didn't do it itself
+                    if frames != packet.dur() && frames == packet.block_dur() {
+                        trim_appended(&mut samples, start, channels, packet.trim_start(), packet.trim_end());
                     }
+                    packets_trimmed |= packet.trim_start() > 0 || packet.trim_end() > 0;
                 }
-                Err(symphonia::core::errors::Error::IoError(_)) => {
-                    // End of stream
+                Err(SymphoniaError::IoError(e)) => {
+                    // A packet cut short by the end of the stream
+                    if policy == DecodeErrorPolicy::Fail {
+                        return Err(DecodeFailure::Corrupt { seconds, reason: e.to_string() }.into());
+                    }
                     break;
                 }
-                Err(symphonia::core::errors::Error::DecodeError(err)) => {
-                    // Log decode error but continue processing to preserve maximum audio data
-                    warn!("decode error in packet (continuing): {}", err);
-                    // Try to insert silence for the expected frame duration to maintain timing
-                    if sample_rate > 0 && channels > 0 {
-                        // Estimate typical packet duration (conservative estimate: 1024 samples per channel)
-                        let estimated_samples_per_channel = 1024;
-                        for _ in 0..(estimated_samples_per_channel * channels) {
-                            samples.push(0.0); // Insert silence to maintain timing
-                        }
-                    }
+                Err(SymphoniaError::DecodeError(err)) => {
+                    // The packet's duration tells exactly how many frames were lost
+                    let missing = ts_to_frames(packet.dur(), time_base, sample_rate);
+                    let reason = format!("decode error: {}", err);
+                    recover(policy, &mut samples, &mut damaged_regions, channels, missing, seconds, reason)?;
                     continue;
                 }
                 Err(e) => {
-                    return Err(anyhow!("Decode error: {}", e));
+                    return Err(Error::unsupported(input, e));
                 }
             }
         }
         
-        if samples.is_empty() {
-            return Err(anyhow!("No audio data decoded"));
-        }
-        
-        Ok(AudioData {
+        segments.push(AudioData {
             samples,
             channels,
             sample_rate,
-        })
+            bits_per_sample,
+            is_float,
+            damaged_regions,
+        });
+        progress.finish();
+        
+        if options.strict {
+            // Count what was actually decoded: a truncated final packet still claims its full duration
+            let decoded: u64 = segments.iter().map(|s| s.frames() as u64).sum();
+            if let Some(expected) = n_frames.filter(|&n| decoded < n) {
+                return Err(DecodeFailure::PrematureEof { decoded, expected }.into());
+            }
+        }
+
+        // Containers without packet-level trimming (e.g. MP4 with iTunSMPB) are trimmed as a whole
+        if let Some(gapless) = gapless.filter(|_| !packets_trimmed) {
+            let last = segments.len() - 1;
+            let first = &mut segments[0];
+            trim_stream(&mut first.samples, &mut first.damaged_regions, first.channels, GaplessInfo { padding: 0, ..gapless });
+            let last = &mut segments[last];
+            trim_stream(&mut last.samples, &mut last.damaged_regions, last.channels, GaplessInfo { delay: 0, ..gapless });
+        }
+
+        segments.retain(|s| !s.samples.is_empty());
+        if segments.is_empty() {
+            return Err(DecodeFailure::Empty.into());
+        }
+
+        let damaged: usize = segments.iter().map(|s| s.damaged_regions.len()).sum();
+        if damaged > 0 {
+            warn!("{} damaged region(s) filled with silence", damaged);
+        }
+        
+        Ok(segments)
     }
     
-    /// Write audio data to the appropriate format based on file extension
+    /// Output path for one of several outputs from a single input: `out.wav` -> `out.{suffix}.wav`
+    pub fn suffixed_output_path(output: &Path, suffix: &str) -> std::path::PathBuf {
+        let stem = output.file_stem().unwrap_or_default().to_string_lossy();
+        let name = match output.extension() {
+            Some(ext) => format!("{}.{}.{}", stem, suffix, ext.to_string_lossy()),
+            None => format!("{}.{}", stem, suffix),
+        };
+        output.with_file_name(name)
+    }
+
+    /// Write audio data with the encoder registered for the output's extension (WAV by default)
     pub fn write_audio_data(
         output: &Path,
         audio_data: &AudioData,
         bit_depth: u16,
     ) -> Result<()> {
-        // Determine output format from file extension
-        let extension = output.extension()
-            .and_then(|ext| ext.to_str())
-            .map(|s| s.to_lowercase())
-            .unwrap_or_default();
-        
-        match extension.as_str() {
-            "mp3" => Self::write_mp3(output, audio_data),
-            "flac" => Self::write_flac(output, audio_data, bit_depth),
-            "wav" | _ => Self::write_wav(output, audio_data, bit_depth), // Default to WAV
-        }
+        FormatRegistry::global().encode(output, audio_data, bit_depth, None)
     }
-    
-    
+
     /// Write audio data to WAV format
-    fn write_wav(
+    pub(crate) fn write_wav(
         output: &Path,
         audio_data: &AudioData,
         bit_depth: u16,
     ) -> Result<()> {
-        let spec = hound::WavSpec {
-            channels: audio_data.channels as u16,
-            sample_rate: audio_data.sample_rate as u32,
-            bits_per_sample: bit_depth,
-            sample_format: if bit_depth == 32 {
-                hound::SampleFormat::Float
-            } else {
-                hound::SampleFormat::Int
-            },
-        };
-        
-        crate::audio_processor::write_wav(output, &spec, &audio_data.samples)
+        crate::audio_processor::write_wav(output, &wav_spec(audio_data, bit_depth), &audio_data.samples)
     }
-    
-    /// Write audio data to MP3 format
-    fn write_mp3(output: &Path, _audio_data: &AudioData) -> Result<()> {
-        // For now, convert MP3 output to WAV format
-        // TODO: Implement proper MP3 encoding once lame API is figured out
-        let wav_path = output.with_extension("wav");
-        Self::write_wav(&wav_path, _audio_data, 16)?;
-        
-        // Could use external tool here if needed
-        warn!("MP3 output requested but not yet implemented. Wrote WAV instead: {}", wav_path.display());
-        Ok(())
-    }
-    
-    /// Write audio data to FLAC format (currently outputs WAV)
-    fn write_flac(
+
+    /// Write audio data as WAV to a stream that cannot seek, such as stdout
+    pub(crate) fn write_wav_stream(
         output: &Path,
+        writer: &mut dyn Write,
         audio_data: &AudioData,
         bit_depth: u16,
     ) -> Result<()> {
-        // For now, output as high-quality WAV instead of FLAC
-        // Use 24-bit for better quality than 16-bit
-        let actual_bit_depth = if bit_depth < 24 { 24 } else { bit_depth };
+        crate::audio_processor::write_wav_stream(writer, &wav_spec(audio_data, bit_depth), &audio_data.samples)
+            .map_err(|e| Error::output(output, e))
+    }
+    
+    /// Write audio data to MP3 format (currently outputs 16-bit WAV)
+    pub(crate) fn write_mp3(output: &Path, audio_data: &AudioData) -> Result<()> {
+        Self::write_wav_instead(output, audio_data, 16, "MP3")
+    }
+    
+    /// Write audio data to FLAC format (currently outputs WAV, at 24 bits or more)
+    pub(crate) fn write_flac(output: &Path, audio_data: &AudioData, bit_depth: u16) -> Result<()> {
+        Self::write_wav_instead(output, audio_data, bit_depth.max(24), "FLAC")
+    }
+
+    /// Write WAV under a `.wav` extension for a format that cannot be encoded yet.
+    ///
+    /// `normalize_to_template` passes the `.wav` path itself, so that the collision
+    /// handling applies to the file actually written.
+    fn write_wav_instead(output: &Path, audio_data: &AudioData, bit_depth: u16, format: &str) -> Result<()> {
         let wav_path = output.with_extension("wav");
-        Self::write_wav(&wav_path, audio_data, actual_bit_depth)?;
+        Self::write_wav(&wav_path, audio_data, bit_depth)?;
+        if wav_path != output {
+            warn!("{} output requested but not yet implemented. Wrote WAV instead: {}", format, wav_path.display());
+        }
         Ok(())
     }
 }
 
+fn wav_spec(audio_data: &AudioData, bit_depth: u16) -> hound::WavSpec {
+    hound::WavSpec {
+        channels: audio_data.channels as u16,
+        sample_rate: audio_data.sample_rate as u32,
+        bits_per_sample: bit_depth,
+        sample_format: if bit_depth == 32 && audio_data.is_float {
+            hound::SampleFormat::Float
+        } else {
+            hound::SampleFormat::Int
+        },
+    }
+}
+
+/// Whether a path means stdin (as INPUT) or stdout (as OUTPUT)
+pub fn is_stdio(path: &Path) -> bool {
+    path.as_os_str() == "-"
+}
+
+/// Encoder delay and padding for a track, from the codec parameters (LAME/Xing header,
+/// Opus pre-skip, Ogg granule positions) or, failing that, an iTunSMPB tag
+fn gapless_info(track: &Track, metadata: &mut ProbedMetadata, format: &mut dyn FormatReader) -> Option<GaplessInfo> {
+    let params = &track.codec_params;
+    if params.delay.is_some() || params.padding.is_some() {
+        return Some(GaplessInfo {
+            delay: params.delay.unwrap_or(0),
+            padding: params.padding.unwrap_or(0),
+        });
+    }
+
+    let from_tags = |revision: Option<&MetadataRevision>| {
+        revision?
+            .tags()
+            .iter()
+            .find(|tag| tag.key.to_lowercase().ends_with("itunsmpb"))
+            .and_then(|tag| parse_itunsmpb(&tag.value.to_string()))
+    };
+    from_tags(metadata.get().as_ref().and_then(|m| m.current()))
+        .or_else(|| from_tags(format.metadata().current()))
+}
+
+/// Parse an iTunSMPB value: space-separated hex words where the second is the
+/// encoder delay and the third the padding, both in frames
+fn parse_itunsmpb(value: &str) -> Option<GaplessInfo> {
+    let mut words = value.split_whitespace().map(|w| u32::from_str_radix(w, 16).ok());
+    let delay = words.nth(1)??;
+    let padding = words.next()??;
+    Some(GaplessInfo { delay, padding })
+}
+
+/// Drop `trim_start` frames from the start and `trim_end` frames from the end of the
+/// samples appended after index `start`
+fn trim_appended(samples: &mut Vec<f32>, start: usize, channels: usize, trim_start: u32, trim_end: u32) {
+    let end = samples.len().saturating_sub(trim_end as usize * channels).max(start);
+    samples.truncate(end);
+    let trimmed_start = (start + trim_start as usize * channels).min(samples.len());
+    samples.drain(start..trimmed_start);
+}
+
+/// Remove encoder delay and padding from a fully decoded stream, moving damaged regions along
+fn trim_stream(samples: &mut Vec<f32>, regions: &mut Vec<DamagedRegion>, channels: usize, gapless: GaplessInfo) {
+    if channels == 0 {
+        return;
+    }
+    trim_appended(samples, 0, channels, gapless.delay, gapless.padding);
+
+    let total = (samples.len() / channels) as u64;
+    let delay = gapless.delay as u64;
+    regions.retain_mut(|region| {
+        let start = region.start_frame.saturating_sub(delay);
+        let end = (region.start_frame + region.frames).saturating_sub(delay).min(total);
+        region.start_frame = start;
+        region.frames = end.saturating_sub(start);
+        region.frames > 0
+    });
+}
+
+fn track_infos(metadata: &mut ProbedMetadata, format: &mut dyn FormatReader) -> Vec<TrackInfo> {
+    let codecs = symphonia::default::get_codecs();
+    let tracks: Vec<Track> = audio_tracks(format.tracks()).cloned().collect();
+
+    tracks
+        .iter()
+        .enumerate()
+        .map(|(index, track)| {
+            let params = &track.codec_params;
+            let codec = codecs
+                .get_codec(params.codec)
+                .map_or_else(|| "unsupported".to_string(), |c| c.short_name.to_string());
+            // PCM demuxers name the sample format in the codec rather than setting it
+            let sample_format = params
+                .sample_format
+                .map(|f| format!("{:?}", f).to_lowercase())
+                .or_else(|| codec.strip_prefix("pcm_").map(str::to_string));
+            TrackInfo {
+                index,
+                id: track.id,
+                codec,
+                language: track.language.clone(),
+                sample_format,
+                bits_per_sample: params.bits_per_sample,
+                channels: params.channels.map(|c| c.count()),
+                channel_layout: params.channels.map(|c| format!("{:?}", c)),
+                sample_rate: params.sample_rate,
+                n_frames: params.n_frames,
+                duration_seconds: match (params.n_frames, params.sample_rate) {
+                    (Some(n), Some(rate)) if rate > 0 => Some(n as f64 / rate as f64),
+                    _ => None,
+                },
+                gapless: gapless_info(track, metadata, format),
+            }
+        })
+        .collect()
+}
+
+fn audio_tracks(tracks: &[Track]) -> impl Iterator<Item = &Track> {
+    tracks.iter().filter(|t| t.codec_params.codec != CODEC_TYPE_NULL)
+}
+
+/// Pick the track to decode: the first audio track unless a selector is given
+fn select_track<'a>(input: &Path, tracks: &'a [Track], selector: Option<&TrackSelector>) -> Result<&'a Track> {
+    let mut candidates = audio_tracks(tracks);
+    let track = match selector {
+        None => candidates.next(),
+        Some(TrackSelector::Index(index)) => candidates.nth(*index),
+        Some(TrackSelector::Language(language)) => candidates.find(|t| {
+            t.language.as_deref().is_some_and(|l| l.eq_ignore_ascii_case(language))
+        }),
+    };
+
+    track.ok_or_else(|| match selector {
+        None => Error::unsupported(input, "no supported audio tracks found"),
+        Some(selector) => Error::TrackNotFound(selector.to_string()),
+    })
+}
+
+/// Which track of a multi-track container to decode
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TrackSelector {
+    /// Position among the audio tracks, as shown by the `tracks` command
+    Index(usize),
+    /// Language code, e.g. `eng`
+    Language(String),
+}
+
+impl std::str::FromStr for TrackSelector {
+    type Err = std::convert::Infallible;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        Ok(match s.parse() {
+            Ok(index) => TrackSelector::Index(index),
+            Err(_) => TrackSelector::Language(s.to_string()),
+        })
+    }
+}
+
+impl std::fmt::Display for TrackSelector {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TrackSelector::Index(index) => write!(f, "{}", index),
+            TrackSelector::Language(language) => write!(f, "{}", language),
+        }
+    }
+}
+
+/// Encoder priming (delay) and trailing padding around the intended samples, in frames
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct GaplessInfo {
+    pub delay: u32,
+    pub padding: u32,
+}
+
+/// Summary of an audio track, as listed by the `tracks` and `info` commands
+#[derive(Debug, Clone, Serialize)]
+pub struct TrackInfo {
+    pub index: usize,
+    pub id: u32,
+    pub codec: String,
+    pub language: Option<String>,
+    pub sample_format: Option<String>,
+    pub bits_per_sample: Option<u32>,
+    pub channels: Option<usize>,
+    pub channel_layout: Option<String>,
+    pub sample_rate: Option<u32>,
+    pub n_frames: Option<u64>,
+    pub duration_seconds: Option<f64>,
+    pub gapless: Option<GaplessInfo>,
+}
+
+/// File-level description produced by the `info` command
+#[derive(Debug, Clone, Serialize)]
+pub struct FileInfo {
+    pub path: String,
+    pub container: Option<String>,
+    pub file_size: u64,
+    /// Average bitrate in bits per second
+    pub bitrate: Option<u64>,
+    pub tracks: Vec<TrackInfo>,
+    pub tags: Vec<TagInfo>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TagInfo {
+    pub key: String,
+    pub value: String,
+}
+
+impl From<&Tag> for TagInfo {
+    fn from(tag: &Tag) -> Self {
+        TagInfo { key: tag.key.clone(), value: tag.value.to_string() }
+    }
+}
+
+/// Container formats recognised from their header bytes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContainerFormat {
+    Wav,
+    Aiff,
+    Caf,
+    Flac,
+    Ogg,
+    Mp3,
+    Adts,
+    Mp4,
+    Matroska,
+}
+
+impl ContainerFormat {
+    /// Read the start of a file and identify its container
+    pub fn detect(input: &Path) -> Result<Option<Self>> {
+        Ok(Self::sniff(&read_header(input)?))
+    }
+
+    /// Identify a container from its first bytes
+    pub fn sniff(header: &[u8]) -> Option<Self> {
+        let at = |offset: usize, magic: &[u8]| header.get(offset..offset + magic.len()) == Some(magic);
+
+        if (at(0, b"RIFF") || at(0, b"RF64")) && at(8, b"WAVE") {
+            Some(ContainerFormat::Wav)
+        } else if at(0, b"FORM") && (at(8, b"AIFF") || at(8, b"AIFC")) {
+            Some(ContainerFormat::Aiff)
+        } else if at(0, b"caff") {
+            Some(ContainerFormat::Caf)
+        } else if at(0, b"fLaC") {
+            Some(ContainerFormat::Flac)
+        } else if at(0, b"OggS") {
+            Some(ContainerFormat::Ogg)
+        } else if at(4, b"ftyp") {
+            Some(ContainerFormat::Mp4)
+        } else if at(0, &[0x1A, 0x45, 0xDF, 0xA3]) {
+            Some(ContainerFormat::Matroska)
+        } else if at(0, b"ID3") {
+            Some(ContainerFormat::Mp3)
+        } else if header.len() >= 2 && header[0] == 0xFF && header[1] & 0xF6 == 0xF0 {
+            // ADTS sync word with layer bits 00
+            Some(ContainerFormat::Adts)
+        } else if header.len() >= 2 && header[0] == 0xFF && header[1] & 0xE0 == 0xE0 {
+            // MPEG audio frame sync
+            Some(ContainerFormat::Mp3)
+        } else {
+            None
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ContainerFormat::Wav => "wav",
+            ContainerFormat::Aiff => "aiff",
+            ContainerFormat::Caf => "caf",
+            ContainerFormat::Flac => "flac",
+            ContainerFormat::Ogg => "ogg",
+            ContainerFormat::Mp3 => "mp3",
+            ContainerFormat::Adts => "aac",
+            ContainerFormat::Mp4 => "mp4",
+            ContainerFormat::Matroska => "matroska",
+        }
+    }
+}
+
+/// Frames between progress updates and interrupt checks when reading WAV through hound or raw PCM
+pub(crate) const PROGRESS_CHUNK_FRAMES: usize = 4096;
+
+/// What to do with a packet that cannot be decoded
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum DecodeErrorPolicy {
+    /// Abort with an error
+    Fail,
+    /// Replace the lost frames with silence to keep timing intact
+    #[default]
+    Silence,
+    /// Drop the lost frames
+    Skip,
+}
+
+/// What to do when the sample rate or channel count changes mid-stream (chained streams)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum FormatChangePolicy {
+    /// Resample and remap later sections to the initial format
+    #[default]
+    Conform,
+    /// Produce a separate output for each section
+    Split,
+}
+
+/// Options controlling track selection and how damaged input is handled while decoding
+#[derive(Debug, Clone, Default)]
+pub struct DecodeOptions {
+    /// Track to decode; defaults to the first audio track
+    pub track: Option<TrackSelector>,
+    /// Input format name or extension, for when neither the content nor the path identify it (e.g. stdin)
+    pub format: Option<String>,
+    /// Layout of headerless PCM input; bypasses format detection
+    pub raw: Option<RawFormat>,
+    pub on_error: DecodeErrorPolicy,
+    pub on_format_change: FormatChangePolicy,
+    /// Verify checksums and fail on any corruption or premature end of stream
+    pub strict: bool,
+}
+
+impl DecodeOptions {
+    /// The effective error policy; strict mode always fails
+    pub fn policy(&self) -> DecodeErrorPolicy {
+        if self.strict { DecodeErrorPolicy::Fail } else { self.on_error }
+    }
+}
+
+/// Errors raised when the input stream is damaged and the decode policy does not allow recovery
+#[derive(Debug, thiserror::Error)]
+pub enum DecodeFailure {
+    #[error("corrupt audio at {seconds:.3}s: {reason}")]
+    Corrupt { seconds: f64, reason: String },
+    #[error("premature end of stream: decoded {decoded} of {expected} frames")]
+    PrematureEof { decoded: u64, expected: u64 },
+    #[error("unreadable container: {reason}")]
+    Unreadable { reason: String },
+    #[error("no audio data decoded")]
+    Empty,
+}
+
+/// Append a decoded buffer to `out` as interleaved f32 samples.
+///
+/// Integer formats are scaled by 2^(bits-1) (unsigned ones after removing their
+/// offset), matching the hound decoder and the WAV encoder.
+fn append_interleaved(decoded: AudioBufferRef, out: &mut Vec<f32>) {
+    match decoded {
+        AudioBufferRef::U8(buf) => interleave(&buf, out, |s| int_to_f32(s as i32 - 0x80, 8)),
+        AudioBufferRef::U16(buf) => interleave(&buf, out, |s| int_to_f32(s as i32 - 0x8000, 16)),
+        AudioBufferRef::U24(buf) => interleave(&buf, out, |s| int_to_f32(s.inner() as i32 - 0x80_0000, 24)),
+        AudioBufferRef::U32(buf) => interleave(&buf, out, |s| int_to_f32((s as i64 - 0x8000_0000) as i32, 32)),
+        AudioBufferRef::S8(buf) => interleave(&buf, out, |s| int_to_f32(s as i32, 8)),
+        AudioBufferRef::S16(buf) => interleave(&buf, out, |s| int_to_f32(s as i32, 16)),
+        AudioBufferRef::S24(buf) => interleave(&buf, out, |s| int_to_f32(s.inner(), 24)),
+        AudioBufferRef::S32(buf) => interleave(&buf, out, |s| int_to_f32(s, 32)),
+        AudioBufferRef::F32(buf) => interleave(&buf, out, |s| s),
+        AudioBufferRef::F64(buf) => interleave(&buf, out, |s| s as f32),
+    }
+}
+
+/// Join decoded segments into one stream, resampling and remapping every segment
+/// to the sample rate and channel count of the first
+fn conform_segments(segments: Vec<AudioData>) -> Result<AudioData> {
+    let mut segments = segments.into_iter();
+    let mut audio_data = segments.next().ok_or(DecodeFailure::Empty)?;
+
+    for segment in segments {
+        let offset = audio_data.frames() as u64;
+        let ratio = audio_data.sample_rate as f64 / segment.sample_rate as f64;
+        let remapped = remap_channels(&segment.samples, segment.channels, audio_data.channels);
+        let resampled = resample(&remapped, audio_data.channels, segment.sample_rate, audio_data.sample_rate)?;
+        audio_data.samples.extend_from_slice(&resampled);
+
+        audio_data.damaged_regions.extend(segment.damaged_regions.iter().map(|region| DamagedRegion {
+            start_frame: offset + (region.start_frame as f64 * ratio).round() as u64,
+            frames: (region.frames as f64 * ratio).round() as u64,
+        }));
+    }
+
+    Ok(audio_data)
+}
+
+/// Handle `frames` frames of input that could not be decoded, according to `policy`
+fn recover(
+    policy: DecodeErrorPolicy,
+    samples: &mut Vec<f32>,
+    regions: &mut Vec<DamagedRegion>,
+    channels: usize,
+    frames: u64,
+    seconds: f64,
+    reason: String,
+) -> Result<()> {
+    match policy {
+        DecodeErrorPolicy::Fail => Err(DecodeFailure::Corrupt { seconds, reason }.into()),
+        DecodeErrorPolicy::Silence => {
+            warn!("{} at {:.3}s ({} frames filled with silence)", reason, seconds, frames);
+            fill_silence(samples, regions, channels, frames);
+            Ok(())
+        }
+        DecodeErrorPolicy::Skip => {
+            warn!("{} at {:.3}s ({} frames skipped)", reason, seconds, frames);
+            Ok(())
+        }
+    }
+}
+
+fn frames_to_seconds(frames: u64, sample_rate: usize) -> f64 {
+    if sample_rate == 0 {
+        return 0.0;
+    }
+    frames as f64 / sample_rate as f64
+}
+
+/// Convert a duration in track timebase units to frames at `sample_rate`.
+/// Without a timebase the units are assumed to already be frames.
+fn ts_to_frames(ts: u64, time_base: Option<TimeBase>, sample_rate: usize) -> u64 {
+    match time_base {
+        Some(tb) if sample_rate > 0 && tb.denom > 0 => {
+            let scaled = ts as u128 * tb.numer as u128 * sample_rate as u128;
+            ((scaled + tb.denom as u128 / 2) / tb.denom as u128) as u64
+        }
+        _ => ts,
+    }
+}
+
+/// Append `frames` frames of silence and record them as damaged,
+/// extending the previous region when the two are contiguous.
+fn fill_silence(samples: &mut Vec<f32>, regions: &mut Vec<DamagedRegion>, channels: usize, frames: u64) {
+    if channels == 0 || frames == 0 {
+        return;
+    }
+
+    let start_frame = (samples.len() / channels) as u64;
+    samples.resize(samples.len() + frames as usize * channels, 0.0);
+
+    match regions.last_mut() {
+        Some(last) if last.start_frame + last.frames == start_frame => last.frames += frames,
+        _ => regions.push(DamagedRegion { start_frame, frames }),
+    }
+}
+
+fn interleave<S: Sample>(buf: &AudioBuffer<S>, out: &mut Vec<f32>, convert: impl Fn(S) -> f32) {
+    let channel_count = buf.spec().channels.count();
+    out.reserve(buf.frames() * channel_count);
+    for frame in 0..buf.frames() {
+        for ch in 0..channel_count {
+            out.push(convert(buf.chan(ch)[frame]));
+        }
+    }
+}
+
 /// Audio data structure
 #[derive(Debug, Clone)]
 pub struct AudioData {
     pub samples: Vec<f32>,
     pub channels: usize,
     pub sample_rate: usize,
+    /// Bit depth of the source; used as the default output bit depth
+    pub bits_per_sample: u16,
+    /// Whether the source stored floating-point samples
+    pub is_float: bool,
+    /// Regions that could not be decoded and were filled with silence
+    pub damaged_regions: Vec<DamagedRegion>,
 }
 
 impl AudioData {
-    #[allow(dead_code)]
+    /// Number of frames (samples per channel)
+    pub fn frames(&self) -> usize {
+        self.samples.len().checked_div(self.channels).unwrap_or(0)
+    }
+
     pub fn duration_seconds(&self) -> f64 {
         self.samples.len() as f64 / (self.channels * self.sample_rate) as f64
     }
+
+    /// Sample peak in dBFS
+    pub fn peak_db(&self) -> f64 {
+        let peak = self.samples.iter().fold(0.0f32, |peak, s| peak.max(s.abs()));
+        if peak <= 0.0 {
+            return f64::NEG_INFINITY;
+        }
+        20.0 * (peak as f64).log10()
+    }
+
+    /// Integrated loudness in LUFS
+    pub fn integrated_lufs(&self) -> Result<f64> {
+        let mut meter = EbuR128::new(
+            self.channels as u32,
+            self.sample_rate as u32,
+            ebur128::Mode::I,
+        )?;
+        
+        meter.add_frames_f32(&self.samples)?;
+        let lufs = meter.loudness_global()?;
+        Ok(lufs)
+    }
+
+    /// Every level measurement of the buffer, from a single pass of the loudness meter
+    pub fn loudness_stats(&self) -> Result<LoudnessStats> {
+        let mode = ebur128::Mode::I | ebur128::Mode::LRA | ebur128::Mode::TRUE_PEAK | ebur128::Mode::SAMPLE_PEAK;
+        let mut meter = EbuR128::new(self.channels as u32, self.sample_rate as u32, mode)?;
+        meter.add_frames_f32(&self.samples)?;
+
+        let (mut true_peak, mut sample_peak) = (0.0f64, 0.0f64);
+        for channel in 0..self.channels as u32 {
+            true_peak = true_peak.max(meter.true_peak(channel)?);
+            sample_peak = sample_peak.max(meter.sample_peak(channel)?);
+        }
+
+        let mean_square = self.samples.iter().map(|&s| (s as f64) * (s as f64)).sum::<f64>() / self.samples.len().max(1) as f64;
+        Ok(LoudnessStats {
+            integrated_lufs: meter.loudness_global()?,
+            true_peak_db: amplitude_to_db(true_peak),
+            sample_peak_db: amplitude_to_db(sample_peak),
+            loudness_range: meter.loudness_range()?,
+            rms_db: amplitude_to_db(mean_square.sqrt()),
+            noise_floor_db: self.noise_floor_db(),
+        })
+    }
+
+    /// RMS level of the quietest 500 ms window that is not digital silence, in dBFS.
+    ///
+    /// This is the "room tone" between phrases that audiobook specs such as ACX limit.
+    /// Returns negative infinity when the whole buffer is digital silence.
+    pub fn noise_floor_db(&self) -> f64 {
+        let window = (self.sample_rate / 2).max(1) * self.channels.max(1);
+        let quietest = self
+            .samples
+            .chunks(window)
+            .map(|w| w.iter().map(|&s| (s as f64) * (s as f64)).sum::<f64>() / w.len() as f64)
+            .filter(|&mean_square| mean_square > 0.0)
+            .fold(f64::INFINITY, f64::min);
+        if quietest.is_finite() { amplitude_to_db(quietest.sqrt()) } else { f64::NEG_INFINITY }
+    }
+
+    /// True (inter-sample) peak in dBTP, from 4x oversampling as in ITU-R BS.1770
+    pub fn true_peak_db(&self) -> Result<f64> {
+        let mut meter = EbuR128::new(
+            self.channels as u32,
+            self.sample_rate as u32,
+            ebur128::Mode::TRUE_PEAK,
+        )?;
+
+        meter.add_frames_f32(&self.samples)?;
+        let mut peak = 0.0f64;
+        for channel in 0..self.channels as u32 {
+            peak = peak.max(meter.true_peak(channel)?);
+        }
+        Ok(amplitude_to_db(peak))
+    }
+}
+
+fn amplitude_to_db(amplitude: f64) -> f64 {
+    if amplitude > 0.0 { 20.0 * amplitude.log10() } else { f64::NEG_INFINITY }
+}
+
+/// Level measurements of a decoded buffer
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct LoudnessStats {
+    /// Integrated loudness (ITU-R BS.1770 gated) in LUFS
+    pub integrated_lufs: f64,
+    /// True peak in dBTP
+    pub true_peak_db: f64,
+    /// Sample peak in dBFS
+    pub sample_peak_db: f64,
+    /// Loudness range (EBU Tech 3342) in LU
+    pub loudness_range: f64,
+    /// RMS level of the whole buffer in dBFS
+    pub rms_db: f64,
+    /// RMS level of the quietest 500 ms that is not digital silence, in dBFS
+    pub noise_floor_db: f64,
+}
+
+/// A stretch of decoded output that was filled with silence because the source was damaged
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DamagedRegion {
+    pub start_frame: u64,
+    pub frames: u64,
+}
+
+impl DamagedRegion {
+    pub fn start_seconds(&self, sample_rate: usize) -> f64 {
+        self.start_frame as f64 / sample_rate as f64
+    }
+
+    pub fn duration_seconds(&self, sample_rate: usize) -> f64 {
+        self.frames as f64 / sample_rate as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::temp_path;
+    use std::path::PathBuf;
+
+    /// A 16-bit stereo WAV of 1000 frames cut off after `keep_bytes` bytes of sample data
+    fn truncated_wav(name: &str, keep_bytes: u64) -> PathBuf {
+        let path = temp_path(name);
+        let spec = hound::WavSpec { channels: 2, sample_rate: 8000, bits_per_sample: 16, sample_format: hound::SampleFormat::Int };
+        let mut writer = hound::WavWriter::create(&path, spec).unwrap();
+        for i in 0..2000 {
+            writer.write_sample((i % 100 * 100) as i16).unwrap();
+        }
+        writer.finalize().unwrap();
+        let file = std::fs::OpenOptions::new().write(true).open(&path).unwrap();
+        file.set_len(44 + keep_bytes).unwrap();
+        path
+    }
+
+    fn decode_truncated(name: &str, on_error: DecodeErrorPolicy) -> Result<AudioData> {
+        // 400 whole frames and half of the next one
+        let path = truncated_wav(name, 400 * 4 + 2);
+        let result = MultiFormatProcessor::decode_wav_with_hound(&path, &DecodeOptions { on_error, ..Default::default() });
+        let _ = std::fs::remove_file(&path);
+        result
+    }
+
+    #[test]
+    fn truncated_wav_is_padded_with_silence_by_default() {
+        let audio = decode_truncated("pad.wav", DecodeErrorPolicy::Silence).unwrap();
+        assert_eq!(audio.frames(), 1000);
+        assert_eq!(audio.damaged_regions, vec![DamagedRegion { start_frame: 400, frames: 600 }]);
+        assert!(audio.samples[800..].iter().all(|&s| s == 0.0));
+    }
+
+    #[test]
+    fn truncated_wav_keeps_the_decoded_frames_with_skip() {
+        let audio = decode_truncated("skip.wav", DecodeErrorPolicy::Skip).unwrap();
+        assert_eq!(audio.frames(), 400);
+        assert!(audio.damaged_regions.is_empty());
+    }
+
+    #[test]
+    fn truncated_wav_fails_with_fail_policy() {
+        match decode_truncated("fail.wav", DecodeErrorPolicy::Fail) {
+            Err(Error::Decode(DecodeFailure::PrematureEof { decoded: 400, expected: 1000 })) => {}
+            other => panic!("expected a premature end of stream, got {:?}", other.map(|a| a.frames())),
+        }
+    }
+
+    #[test]
+    fn sniff_recognizes_containers_by_magic_bytes() {
+        let cases: [(&[u8], Option<ContainerFormat>); 11] = [
+            (b"RIFF\x24\0\0\0WAVEfmt ", Some(ContainerFormat::Wav)),
+            (b"RF64\xff\xff\xff\xffWAVEds64", Some(ContainerFormat::Wav)),
+            (b"FORM\0\0\0\0AIFC", Some(ContainerFormat::Aiff)),
+            (b"caff\0\x01\0\0", Some(ContainerFormat::Caf)),
+            (b"fLaC\0\0\0\x22", Some(ContainerFormat::Flac)),
+            (b"OggS\0\x02", Some(ContainerFormat::Ogg)),
+            (b"\0\0\0\x20ftypM4A ", Some(ContainerFormat::Mp4)),
+            (b"ID3\x04\0", Some(ContainerFormat::Mp3)),
+            (&[0xFF, 0xF1, 0x50], Some(ContainerFormat::Adts)),
+            (&[0xFF, 0xFB, 0x90], Some(ContainerFormat::Mp3)),
+            (b"RIFF\0\0\0\0AVI ", None),
+        ];
+        for (header, expected) in cases {
+            assert_eq!(ContainerFormat::sniff(header), expected, "{:?}", header);
+        }
+        assert_eq!(ContainerFormat::sniff(b"RIF"), None);
+    }
+
+    #[test]
+    fn strict_implies_fail_policy() {
+        let options = DecodeOptions { strict: true, on_error: DecodeErrorPolicy::Skip, ..Default::default() };
+        assert_eq!(options.policy(), DecodeErrorPolicy::Fail);
+    }
+
+    #[test]
+    fn timestamps_convert_to_frames_through_the_time_base() {
+        // Without a time base, timestamps already count frames
+        assert_eq!(ts_to_frames(1234, None, 44100), 1234);
+        assert_eq!(ts_to_frames(1234, Some(TimeBase::new(1, 44100)), 44100), 1234);
+        // Milliseconds at 48 kHz, and 90 kHz ticks at 44.1 kHz
+        assert_eq!(ts_to_frames(20, Some(TimeBase::new(1, 1000)), 48000), 960);
+        assert_eq!(ts_to_frames(90000, Some(TimeBase::new(1, 90000)), 44100), 44100);
+        // Rounded to the nearest frame
+        assert_eq!(ts_to_frames(1, Some(TimeBase::new(1, 3)), 1), 0);
+        assert_eq!(ts_to_frames(2, Some(TimeBase::new(1, 3)), 1), 1);
+        // No overflow for a day of 1/90000 ticks at 192 kHz
+        assert_eq!(ts_to_frames(90000 * 86400, Some(TimeBase::new(1, 90000)), 192000), 192000 * 86400);
+        // An unknown sample rate leaves the timestamp alone
+        assert_eq!(ts_to_frames(20, Some(TimeBase::new(1, 1000)), 0), 20);
+    }
+
+    #[test]
+    fn silence_is_appended_and_recorded_where_it_starts() {
+        let mut samples = vec![0.5; 6];
+        let mut regions = Vec::new();
+        fill_silence(&mut samples, &mut regions, 2, 4);
+        assert_eq!(samples, [0.5, 0.5, 0.5, 0.5, 0.5, 0.5, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0]);
+        assert_eq!(regions, [DamagedRegion { start_frame: 3, frames: 4 }]);
+
+        // A gap right after the last one extends it
+        fill_silence(&mut samples, &mut regions, 2, 2);
+        assert_eq!(regions, [DamagedRegion { start_frame: 3, frames: 6 }]);
+
+        // A gap after more decoded audio is a region of its own
+        samples.extend([0.25; 4]);
+        fill_silence(&mut samples, &mut regions, 2, 1);
+        assert_eq!(regions, [DamagedRegion { start_frame: 3, frames: 6 }, DamagedRegion { start_frame: 11, frames: 1 }]);
+        assert_eq!(samples.len(), 24);
+    }
+
+    #[test]
+    fn itunsmpb_gives_delay_and_padding() {
+        // As written by iTunes for an AAC file: 2112 frames of delay, 458 of padding
+        let tag = " 00000000 00000840 000001CA 00000000003F31F6 00000000 00000000 00000000 00000000 00000000 00000000 00000000 00000000";
+        assert_eq!(parse_itunsmpb(tag), Some(GaplessInfo { delay: 2112, padding: 458 }));
+        assert_eq!(parse_itunsmpb("0 840 1ca"), Some(GaplessInfo { delay: 2112, padding: 458 }));
+    }
+
+    #[test]
+    fn malformed_itunsmpb_is_ignored() {
+        for tag in ["", "00000000", "00000000 00000840", "00000000 0000084G 000001CA", "00000000 00000840 -1", "0 100000000 0"] {
+            assert_eq!(parse_itunsmpb(tag), None, "{:?}", tag);
+        }
+    }
+
+    #[test]
+    fn trim_removes_delay_and_padding() {
+        // Stereo frames numbered 0..10
+        let mut samples: Vec<f32> = (0..10).flat_map(|f| [f as f32, -(f as f32)]).collect();
+        let mut regions = Vec::new();
+        trim_stream(&mut samples, &mut regions, 2, GaplessInfo { delay: 3, padding: 2 });
+        assert_eq!(samples, [3.0, -3.0, 4.0, -4.0, 5.0, -5.0, 6.0, -6.0, 7.0, -7.0]);
+    }
+
+    #[test]
+    fn trim_longer_than_the_stream_leaves_nothing() {
+        let mut samples = vec![0.5; 20];
+        let mut regions = vec![DamagedRegion { start_frame: 2, frames: 4 }];
+        trim_stream(&mut samples, &mut regions, 2, GaplessInfo { delay: 8, padding: 8 });
+        assert!(samples.is_empty() && regions.is_empty());
+
+        let mut samples = vec![0.5; 20];
+        trim_stream(&mut samples, &mut regions, 2, GaplessInfo { delay: 0, padding: 11 });
+        assert!(samples.is_empty());
+    }
+
+    #[test]
+    fn trim_moves_and_clips_damaged_regions() {
+        // 100 mono frames, trimmed to frames 10..90
+        let mut samples = vec![0.5; 100];
+        let mut regions = vec![
+            DamagedRegion { start_frame: 0, frames: 5 },   // all in the delay
+            DamagedRegion { start_frame: 8, frames: 4 },   // straddles the start
+            DamagedRegion { start_frame: 40, frames: 10 }, // inside
+            DamagedRegion { start_frame: 85, frames: 10 }, // straddles the end
+            DamagedRegion { start_frame: 95, frames: 5 },  // all in the padding
+        ];
+        trim_stream(&mut samples, &mut regions, 1, GaplessInfo { delay: 10, padding: 10 });
+        assert_eq!(samples.len(), 80);
+        assert_eq!(
+            regions,
+            [
+                DamagedRegion { start_frame: 0, frames: 2 },
+                DamagedRegion { start_frame: 30, frames: 10 },
+                DamagedRegion { start_frame: 75, frames: 5 },
+            ]
+        );
+    }
+
+    #[test]
+    fn empty_gaps_are_not_recorded() {
+        let mut samples = vec![0.5; 4];
+        let mut regions = Vec::new();
+        fill_silence(&mut samples, &mut regions, 2, 0);
+        fill_silence(&mut samples, &mut regions, 0, 10);
+        assert_eq!((samples.len(), regions.len()), (4, 0));
+    }
 }
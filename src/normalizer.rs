@@ -1,8 +1,18 @@
-use std::path::Path;
-use anyhow::Result;
-use crate::multi_format_processor::MultiFormatProcessor;
-use crate::fade::{apply_fades, FadeCurve};
-use tracing::{info, warn};
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use crate::error::{Error, Result};
+use crate::interrupt;
+use crate::multi_format_processor::{is_stdio, AudioData, DecodeErrorPolicy, DecodeOptions, MultiFormatProcessor};
+use crate::raw::{encode_raw, RawFormat, RawSampleFormat};
+use crate::registry::{hound_can_read, FormatRegistry};
+use crate::fade::{Fade, FadeCurve};
+use crate::template::{OutputTemplate, TemplateValues};
+use crate::processor::{Chain, Gain, HighPass, Limiter, Processor, StageSpec, Trim};
+use crate::audio_processor::copy_wav_pcm;
+use serde::{Deserialize, Serialize};
+use std::time::Instant;
+use tracing::{debug, info, info_span, warn};
 
 fn linear_to_db(x: f32) -> f32 { if x <= 0.0 { f32::NEG_INFINITY } else { 20.0 * x.log10() } }
 fn db_to_linear(db: f32) -> f32 { (10.0f32).powf(db / 20.0) }
@@ -42,8 +52,100 @@ fn would_clip_with_lufs_gain(audio_data: &[f32], lufs_gain_db: f32) -> bool {
     false
 }
 
+/// Settings shared by peak and LUFS normalization
+#[derive(Debug, Clone, Default)]
+pub struct NormalizeOptions {
+    /// Fade in duration in seconds
+    pub fade_in: f64,
+    /// Fade out duration in seconds
+    pub fade_out: f64,
+    pub fade_curve: FadeCurve,
+    /// Allow clipping to reach the exact LUFS target
+    pub force_clip: bool,
+    pub decode: DecodeOptions,
+    /// Processing stages in order; empty means `StageSpec::DEFAULT_CHAIN`
+    pub chain: Vec<StageSpec>,
+    /// Output format name or extension; defaults to the output's extension
+    pub output_format: Option<String>,
+    /// Write headerless PCM in this sample format instead of a container
+    pub raw_output: Option<RawSampleFormat>,
+    /// What to do when an output file already exists
+    pub on_collision: CollisionPolicy,
+    /// Allow the output to be the input file itself
+    pub in_place: bool,
+    /// Measure and plan every output, but write nothing
+    pub dry_run: bool,
+    /// Decode each written output and fail if its level is further than this many dB from the target
+    pub verify: Option<f64>,
+    /// Highest true peak (dBTP) LUFS normalization may produce, instead of keeping sample peaks below 0 dBFS
+    pub true_peak_ceiling: Option<f64>,
+    /// Output paths reserved across the files of a batch; `None` reserves them for one call only
+    pub claims: Option<Arc<OutputClaims>>,
+}
+
+/// Output paths reserved by the files of one run, so that parallel files rendering to the
+/// same name collide as if the first one had already been written
+#[derive(Debug, Default)]
+pub struct OutputClaims(Mutex<BTreeSet<PathBuf>>);
+
+impl OutputClaims {
+    /// Reserve an output path, reporting whether it was free, exists on disk or was reserved before.
+    ///
+    /// An existing file is reserved too, so that a second output replacing it collides with
+    /// the first even under `CollisionPolicy::Overwrite`.
+    pub fn claim(&self, output: &Path) -> Claim {
+        let mut claimed = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        let key = std::path::absolute(output).unwrap_or_else(|_| output.to_path_buf());
+        if !claimed.insert(key) {
+            Claim::Claimed
+        } else if output.exists() {
+            Claim::Exists
+        } else {
+            Claim::Free
+        }
+    }
+}
+
+/// Result of `OutputClaims::claim`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Claim {
+    /// Nothing is at the path yet
+    Free,
+    /// A file exists at the path
+    Exists,
+    /// Another output of the run reserved the path before
+    Claimed,
+}
+
+/// Level to normalize to
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Target {
+    /// Sample peak in dBFS
+    Peak(f64),
+    /// Integrated loudness in LUFS
+    Lufs(f64),
+}
+
+impl Target {
+    /// The target level, in dBFS or LUFS
+    pub fn level(&self) -> f64 {
+        match *self {
+            Target::Peak(db) => db,
+            Target::Lufs(lufs) => lufs,
+        }
+    }
+
+    /// `"peak"` or `"lufs"`
+    pub fn mode(&self) -> &'static str {
+        match self {
+            Target::Peak(_) => "peak",
+            Target::Lufs(_) => "lufs",
+        }
+    }
+}
+
 /// Results of clipping analysis
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, Serialize)]
 pub struct ClippingAnalysis {
     pub would_clip: bool,
     pub max_safe_lufs: f32,
@@ -51,10 +153,454 @@ pub struct ClippingAnalysis {
     pub headroom_db: f32,
 }
 
-pub fn normalize_peak(input: &Path, output: &Path, target_peak_db: f64, fade_in: f64, fade_out: f64, curve: &str) -> Result<()> {
-    // Decode input file using multi-format processor
-    let mut audio_data = MultiFormatProcessor::decode_audio_to_f32(input)?;
+/// What a normalization measured and applied
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct NormalizeReport {
+    /// Level before processing: sample peak in dBFS, or integrated loudness in LUFS
+    pub measured: f64,
+    /// Target that was asked for
+    pub requested_target: f64,
+    /// Target actually used; lower than requested after a safety adjustment
+    pub applied_target: f64,
+    /// Gain applied in dB
+    pub gain_db: f64,
+    /// Clipping analysis, for LUFS normalization only
+    pub clipping: Option<ClippingAnalysis>,
+}
+
+impl NormalizeReport {
+    /// Whether the LUFS target was lowered to avoid clipping
+    pub fn safety_adjusted(&self) -> bool {
+        self.applied_target != self.requested_target
+    }
+
+    /// Gain in dB that would reach the requested target exactly
+    pub fn requested_gain_db(&self) -> f64 {
+        self.requested_target - self.measured
+    }
+}
+
+/// Outcome of normalizing a file into one output
+#[derive(Debug, Clone, Serialize)]
+pub struct OutputReport {
+    /// Where the audio was written, after templating and collision handling; for a skipped
+    /// output, the file that already existed
+    pub path: PathBuf,
+    /// What the normalization measured and applied; `None` when the output was skipped
+    pub normalize: Option<NormalizeReport>,
+    /// Measurements of the written file, when `NormalizeOptions::verify` is set
+    pub verification: Option<Verification>,
+    /// Set when the output was already taken and is neither skipped nor suffixed
+    pub collision: Option<Collision>,
+}
+
+/// How an output that is already taken (on disk, or by another file of the batch) is handled
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Collision {
+    /// A run with `CollisionPolicy::Fail` fails the file; only reported by dry runs
+    Exists,
+    /// `CollisionPolicy::Overwrite` replaces the existing file
+    Overwrite,
+    /// Another output of the run writes the same path, so a run with `CollisionPolicy::Fail`
+    /// or `Overwrite` fails the file; only reported by dry runs
+    Claimed,
+}
+
+/// Re-measurement of a written output against the level it was normalized to
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Verification {
+    /// Level the output should have: the applied target (LUFS, or sample peak dBFS)
+    pub intended: f64,
+    /// The same level measured in the written file
+    pub achieved: f64,
+    /// Integrated loudness of the written file in LUFS
+    pub integrated_lufs: f64,
+    /// True peak of the written file in dBTP
+    pub true_peak_db: f64,
+    /// Largest accepted difference between `intended` and `achieved`, in dB
+    pub tolerance: f64,
+}
+
+impl Verification {
+    pub fn passed(&self) -> bool {
+        (self.achieved - self.intended).abs() <= self.tolerance
+    }
+}
+
+impl OutputReport {
+    /// Whether the output already existed and `CollisionPolicy::Skip` left it alone
+    pub fn skipped(&self) -> bool {
+        self.normalize.is_none()
+    }
+}
+
+/// What to do when an output file already exists
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum CollisionPolicy {
+    /// Fail the file
+    #[default]
+    Fail,
+    /// Leave the existing file alone and move on
+    Skip,
+    /// Write to `NAME.1.EXT`, `NAME.2.EXT`, ... instead
+    Suffix,
+    /// Replace the existing file
+    Overwrite,
+}
+
+/// Peak-normalize a file, writing one report per output (see `normalize_to_template`)
+pub fn normalize_peak(input: &Path, output: &Path, target_peak_db: f64, options: &NormalizeOptions) -> Result<Vec<OutputReport>> {
+    normalize_to_template(input, &OutputTemplate::literal(output), Target::Peak(target_peak_db), options)
+}
+
+/// LUFS-normalize a file, writing one report per output (see `normalize_to_template`)
+pub fn normalize_lufs(input: &Path, output: &Path, target_lufs: f64, options: &NormalizeOptions) -> Result<Vec<OutputReport>> {
+    normalize_to_template(input, &OutputTemplate::literal(output), Target::Lufs(target_lufs), options)
+}
+
+/// Normalize a file to the path rendered from `output` for it.
+///
+/// There is one report per output: a chained stream split at format changes writes each
+/// section to its own `.partN` output. Existing outputs are handled by `options.on_collision`;
+/// with `Skip`, the input is not even decoded when the path does not depend on measurement.
+pub fn normalize_to_template(input: &Path, output: &OutputTemplate, target: Target, options: &NormalizeOptions) -> Result<Vec<OutputReport>> {
+    // Everything logged while processing this input carries its path
+    let _span = info_span!("file", input = %input.display()).entered();
+    let started = Instant::now();
+    let mut chain = build_chain(target, options)?;
+    debug!("processing chain: {}", chain.names().join(" -> "));
+
+    let values = |measured| TemplateValues { target: target.level(), measured };
+    if options.on_collision == CollisionPolicy::Skip && !output.needs_measurement() {
+        let (path, _) = stand_in_output(output.render(input, &values(None)), options)?;
+        if !is_stdio(&path) && path.exists() && !(options.in_place && is_same_file(input, &path)) {
+            info!("skipping {}: {} already exists", input.display(), path.display());
+            return Ok(vec![OutputReport { path, normalize: None, verification: None, collision: None }]);
+        }
+    }
+
+    let segments = MultiFormatProcessor::decode_audio_segments(input, &options.decode)?;
+    let split = segments.len() > 1;
+    if split {
+        info!("splitting output into {} parts at format changes", segments.len());
+    }
+
+    // Process every section before resolving and writing any output, so that a collision
+    // or processing error does not leave a partial set of parts behind
+    let mut processed = Vec::new();
+    for (i, mut audio_data) in segments.into_iter().enumerate() {
+        let report = run_chain(&mut chain, &mut audio_data)?;
+        let mut path = output.render(input, &values(Some(report.measured)));
+        if split {
+            if is_stdio(&path) {
+                return Err(Error::Usage("cannot split into several outputs when writing to stdout; use --on-format-change conform".to_string()));
+            }
+            path = MultiFormatProcessor::suffixed_output_path(&path, &format!("part{}", i + 1));
+        }
+        if options.verify.is_some() && !options.dry_run && is_stdio(&path) {
+            return Err(Error::Usage("cannot verify output written to stdout".to_string()));
+        }
+        let (path, stand_in) = stand_in_output(path, options)?;
+        processed.push((audio_data, report, chain.is_transparent(), path, stand_in));
+    }
+
+    let call_claims = OutputClaims::default();
+    let claims = options.claims.as_deref().unwrap_or(&call_claims);
+    let mut resolved = Vec::new();
+    for (audio_data, report, transparent, path, stand_in) in processed {
+        let resolved_path = resolve_output(input, &path, options, claims)?;
+        resolved.push((audio_data, report, transparent, stand_in, path, resolved_path));
+    }
+
+    // Write all parts or none
+    interrupt::check()?;
+    let mut reports = Vec::new();
+    for (audio_data, report, transparent, stand_in, path, resolved_path) in resolved {
+        let Some((path, collision)) = resolved_path else {
+            info!("skipping {}: {} already exists", input.display(), path.display());
+            reports.push(OutputReport { path, normalize: None, verification: None, collision: None });
+            continue;
+        };
+        if options.dry_run {
+            debug!("dry run: not writing {}", path.display());
+            reports.push(OutputReport { path, normalize: Some(report), verification: None, collision });
+            continue;
+        }
+
+        if output.has_placeholders() {
+            create_parent_dir(&path)?;
+        }
+        let format = stand_in.or(options.output_format.as_deref());
+        if !(transparent && try_passthrough(input, &path, &audio_data, format, options)?) {
+            write_output(&path, &audio_data, format, options)?;
+        }
+        if let Some(format) = stand_in {
+            warn!("{} output requested but not yet implemented. Wrote WAV instead: {}", format.to_uppercase(), path.display());
+        }
+        // A stand-in wrote WAV, which the extension names
+        let written_format = if stand_in.is_some() { None } else { options.output_format.as_deref() };
+        let verification = match options.verify {
+            Some(tolerance) => Some(verify_output(&path, written_format, &audio_data, target, &report, tolerance, options)?),
+            None => None,
+        };
+        info!(
+            output = %path.display(),
+            mode = target.mode(),
+            measured = report.measured,
+            target = report.applied_target,
+            gain_db = report.gain_db,
+            safety_adjusted = report.safety_adjusted(),
+            duration_s = audio_data.duration_seconds(),
+            elapsed_s = started.elapsed().as_secs_f64(),
+            "wrote {}",
+            path.display()
+        );
+        reports.push(OutputReport { path, normalize: Some(report), verification, collision });
+    }
+    Ok(reports)
+}
+
+/// Decode a written output and check that it reached the applied target within `tolerance` dB.
+///
+/// This catches what the computed gain does not account for: fades, clamping, limiting and
+/// quantisation to the output bit depth. `path` is the file the encoder wrote, read back in
+/// the format it was written in, which `--output-format` may have chosen over the extension.
+fn verify_output(
+    path: &Path,
+    format: Option<&str>,
+    audio_data: &AudioData,
+    target: Target,
+    report: &NormalizeReport,
+    tolerance: f64,
+    options: &NormalizeOptions,
+) -> Result<Verification> {
+    let decode = DecodeOptions {
+        format: format.map(str::to_string),
+        raw: options.raw_output.map(|sample_format| RawFormat {
+            sample_format,
+            channels: audio_data.channels,
+            sample_rate: audio_data.sample_rate,
+        }),
+        ..Default::default()
+    };
+    let written = MultiFormatProcessor::decode_audio_with_options(path, &decode)?;
+    let integrated_lufs = written.integrated_lufs()?;
+    let achieved = match target {
+        Target::Lufs(_) => integrated_lufs,
+        Target::Peak(_) => written.peak_db(),
+    };
+
+    let verification = Verification {
+        intended: report.applied_target,
+        achieved,
+        integrated_lufs,
+        true_peak_db: written.true_peak_db()?,
+        tolerance,
+    };
+    debug!("verified {}: {:?}", path.display(), verification);
+    if !verification.passed() {
+        let unit = match target {
+            Target::Lufs(_) => "LUFS",
+            Target::Peak(_) => "dBFS peak",
+        };
+        return Err(Error::Verification {
+            path: path.to_path_buf(),
+            reason: format!(
+                "measured {:.2} {}, intended {:.2} {} (tolerance {:.2} dB)",
+                achieved, unit, verification.intended, unit, tolerance
+            ),
+        });
+    }
+    Ok(verification)
+}
+
+/// Apply `options.on_collision` to an output path, returning the path to write and the
+/// collision it ran into, or `None` to skip.
+///
+/// The input itself is only ever replaced when `in_place` is set. Outputs are written to a
+/// temporary file and renamed into place, so replacing the input is safe once allowed. A dry
+/// run reports a collision that would fail the file instead of failing, so the plan lists
+/// every one of them.
+fn resolve_output(
+    input: &Path,
+    output: &Path,
+    options: &NormalizeOptions,
+    claims: &OutputClaims,
+) -> Result<Option<(PathBuf, Option<Collision>)>> {
+    if is_stdio(output) {
+        return Ok(Some((output.to_path_buf(), None)));
+    }
+    let same_file = !is_stdio(input) && is_same_file(input, output);
+    if same_file && options.in_place {
+        return Ok(Some((output.to_path_buf(), None)));
+    }
+    let claim = claims.claim(output);
+    if claim == Claim::Free {
+        return Ok(Some((output.to_path_buf(), None)));
+    }
+    // Overwriting only ever replaces a file from before the run, never another output of it
+    match (options.on_collision, claim) {
+        (CollisionPolicy::Fail | CollisionPolicy::Overwrite, _) if same_file => Err(Error::Usage(format!(
+            "output {} is the input file; use --in-place to replace it", output.display()
+        ))),
+        (CollisionPolicy::Overwrite, Claim::Exists) => Ok(Some((output.to_path_buf(), Some(Collision::Overwrite)))),
+        (CollisionPolicy::Fail | CollisionPolicy::Overwrite, claim) if options.dry_run => {
+            let collision = if claim == Claim::Claimed { Collision::Claimed } else { Collision::Exists };
+            Ok(Some((output.to_path_buf(), Some(collision))))
+        }
+        (CollisionPolicy::Fail | CollisionPolicy::Overwrite, Claim::Claimed) => {
+            Err(Error::output(output, "another input of this run writes the same file; use --on-collision suffix or a template that keeps the names apart"))
+        }
+        (CollisionPolicy::Fail | CollisionPolicy::Overwrite, _) => {
+            Err(Error::output(output, "file already exists; use --overwrite to replace it"))
+        }
+        (CollisionPolicy::Skip, _) => Ok(None),
+        (CollisionPolicy::Suffix, _) => Ok(Some((
+            (1..)
+                .map(|n| MultiFormatProcessor::suffixed_output_path(output, &n.to_string()))
+                .find(|candidate| claims.claim(candidate) == Claim::Free)
+                .expect("unbounded suffix search"),
+            None,
+        ))),
+    }
+}
+
+/// The path to write an output to, and the name of the stand-in encoder writing it.
+///
+/// MP3 and FLAC cannot be encoded yet; their encoders write WAV under a `.wav` extension
+/// instead. The path is changed before collisions are resolved, so that `on_collision`
+/// and the claims of a batch apply to the file actually written.
+fn stand_in_output(path: PathBuf, options: &NormalizeOptions) -> Result<(PathBuf, Option<&'static str>)> {
+    if options.raw_output.is_some() || is_stdio(&path) {
+        return Ok((path, None));
+    }
+    let encoder = FormatRegistry::global().encoder_for(&path, options.output_format.as_deref())?;
+    if encoder.supported() {
+        return Ok((path, None));
+    }
+    Ok((path.with_extension("wav"), Some(encoder.name())))
+}
+
+fn is_same_file(a: &Path, b: &Path) -> bool {
+    match (a.canonicalize(), b.canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+/// Create the directories a templated output lives in
+fn create_parent_dir(output: &Path) -> Result<()> {
+    match output.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => std::fs::create_dir_all(dir).map_err(|e| Error::output(output, e)),
+        _ => Ok(()),
+    }
+}
+
+/// Build the processing chain for `options.chain`, which must contain exactly one `normalize` stage
+pub fn build_chain(target: Target, options: &NormalizeOptions) -> Result<Chain> {
+    let specs = if options.chain.is_empty() { &StageSpec::DEFAULT_CHAIN[..] } else { &options.chain[..] };
+    if specs.iter().filter(|s| **s == StageSpec::Normalize).count() != 1 {
+        return Err(Error::Usage("the processing chain must contain exactly one normalize stage".to_string()));
+    }
+
+    let mut builder = Chain::builder();
+    for spec in specs {
+        builder = match *spec {
+            StageSpec::Trim { threshold_db } => builder.stage(Trim::new(threshold_db)),
+            StageSpec::HighPass { cutoff_hz } => builder.stage(HighPass::new(cutoff_hz)),
+            StageSpec::Gain { db } => builder.stage(Gain::new(db)),
+            StageSpec::Normalize => builder.stage(Normalize {
+                ceiling_db: options.true_peak_ceiling,
+                limited: limited(specs),
+                ..Normalize::new(target, options.force_clip)
+            }),
+            StageSpec::Limiter { ceiling_db } => builder.stage(Limiter::new(ceiling_db)),
+            StageSpec::Fade => builder.stage(Fade { fade_in: options.fade_in, fade_out: options.fade_out, curve: options.fade_curve }),
+        };
+    }
+    Ok(builder.build())
+}
+
+/// Whether a limiter follows the normalize stage
+fn limited(specs: &[StageSpec]) -> bool {
+    specs
+        .iter()
+        .skip_while(|s| **s != StageSpec::Normalize)
+        .any(|s| matches!(s, StageSpec::Limiter { .. }))
+}
+
+fn run_chain(chain: &mut Chain, audio_data: &mut AudioData) -> Result<NormalizeReport> {
+    chain
+        .process(audio_data)?
+        .ok_or_else(|| Error::Usage("the processing chain has no normalize stage".to_string()))
+}
+
+/// Peak-normalize an in-memory buffer, running the processing chain from `options`
+pub fn normalize_peak_buffer(audio_data: &mut AudioData, target_peak_db: f64, options: &NormalizeOptions) -> Result<NormalizeReport> {
+    run_chain(&mut build_chain(Target::Peak(target_peak_db), options)?, audio_data)
+}
+
+/// LUFS-normalize an in-memory buffer, running the processing chain from `options`.
+///
+/// Unless `force_clip` is set, the target is lowered to the loudest level that does not clip.
+pub fn normalize_lufs_buffer(audio_data: &mut AudioData, target_lufs: f64, options: &NormalizeOptions) -> Result<NormalizeReport> {
+    run_chain(&mut build_chain(Target::Lufs(target_lufs), options)?, audio_data)
+}
+
+/// Peak or LUFS normalization as a chain stage
+#[derive(Debug, Clone)]
+pub struct Normalize {
+    pub target: Target,
+    /// Allow clipping to reach the exact LUFS target
+    pub force_clip: bool,
+    /// True-peak ceiling (dBTP) for the LUFS safety adjustment; `None` keeps sample peaks below 0 dBFS
+    pub ceiling_db: Option<f64>,
+    /// A limiter later in the chain controls peaks, so apply the full LUFS gain without clamping
+    pub limited: bool,
+    report: Option<NormalizeReport>,
+}
+
+impl Normalize {
+    pub fn new(target: Target, force_clip: bool) -> Self {
+        Normalize { target, force_clip, ceiling_db: None, limited: false, report: None }
+    }
+}
+
+impl Processor for Normalize {
+    fn name(&self) -> &'static str {
+        "normalize"
+    }
+
+    fn process(&mut self, audio_data: &mut AudioData) -> Result<()> {
+        let report = match self.target {
+            Target::Peak(target_peak_db) => peak_gain(audio_data, target_peak_db),
+            Target::Lufs(target_lufs) => lufs_gain(audio_data, target_lufs, self)?,
+        };
+
+        let gain = db_to_linear(report.gain_db as f32);
+        if self.limited {
+            for v in &mut audio_data.samples { *v *= gain; }
+        } else {
+            for v in &mut audio_data.samples { *v = (*v * gain).clamp(-1.0, 1.0); }
+        }
+
+        self.report = Some(report);
+        Ok(())
+    }
+
+    fn is_transparent(&self) -> bool {
+        self.report.is_some_and(|r| db_to_linear(r.gain_db as f32) == 1.0)
+    }
+
+    fn report(&self) -> Option<NormalizeReport> {
+        self.report
+    }
+}
 
+/// Measure the sample peak and the gain that brings it to the target
+fn peak_gain(audio_data: &AudioData, target_peak_db: f64) -> NormalizeReport {
     // Find current peak
     let mut peak = 0.0f32;
     for v in &audio_data.samples { peak = peak.max(v.abs()); }
@@ -62,32 +608,45 @@ pub fn normalize_peak(input: &Path, output: &Path, target_peak_db: f64, fade_in:
     // Compute gain
     let current_peak_db = linear_to_db(peak);
     let gain_db = target_peak_db as f32 - current_peak_db;
-    let gain = db_to_linear(gain_db);
-
-    for v in &mut audio_data.samples { *v = (*v * gain).clamp(-1.0, 1.0); }
-
-    // Apply fades
-    let curve = FadeCurve::from_str(curve);
-    apply_fades(&mut audio_data.samples, audio_data.channels, audio_data.sample_rate, fade_in, fade_out, curve);
 
-    // Write output in the format determined by file extension
-    MultiFormatProcessor::write_audio_data(output, &audio_data, 16)?;
-    Ok(())
+    NormalizeReport {
+        measured: current_peak_db as f64,
+        requested_target: target_peak_db,
+        applied_target: target_peak_db,
+        gain_db: gain_db as f64,
+        clipping: None,
+    }
 }
 
-pub fn normalize_lufs(input: &Path, output: &Path, target_lufs: f64, fade_in: f64, fade_out: f64, curve: &str, force_clip: bool) -> Result<()> {
-    // Decode input file using multi-format processor
-    let mut audio_data = MultiFormatProcessor::decode_audio_to_f32(input)?;
-
-    // Measure LUFS using multi-format processor
-    let current_lufs = MultiFormatProcessor::get_lufs_level(input)? as f32;
+/// Measure integrated loudness and the gain that brings it to the target, lowered to avoid clipping
+/// (or exceeding the true-peak ceiling) unless forced or left to a limiter
+fn lufs_gain(audio_data: &AudioData, target_lufs: f64, stage: &Normalize) -> Result<NormalizeReport> {
+    // Measure LUFS on the decoded data
+    let current_lufs = audio_data.integrated_lufs()? as f32;
+    if !current_lufs.is_finite() {
+        // Silence, or less than one 400 ms gating block of audio
+        return Err(Error::LoudnessTarget {
+            target: target_lufs,
+            reason: "integrated loudness of the input cannot be measured".to_string(),
+        });
+    }
     let target_lufs_f32 = target_lufs as f32;
     let requested_gain_db = target_lufs_f32 - current_lufs;
     
     // Perform clipping analysis
-    let analysis = analyze_clipping_risk(&audio_data.samples, current_lufs, target_lufs_f32);
+    let analysis = match stage.ceiling_db {
+        Some(ceiling_db) => analyze_true_peak_risk(audio_data, current_lufs, target_lufs_f32, ceiling_db as f32)?,
+        None => analyze_clipping_risk(&audio_data.samples, current_lufs, target_lufs_f32),
+    };
+    let force_clip = stage.force_clip;
     
-    let (final_target_lufs, actual_gain_db) = if analysis.would_clip && !force_clip {
+    let (final_target_lufs, actual_gain_db) = if analysis.would_clip && stage.limited {
+        // The limiter that follows brings the peaks down
+        info!("target LUFS {:.2} exceeds the peak headroom ({:.2} dB); leaving the peaks to the limiter",
+              target_lufs_f32, analysis.headroom_db);
+
+        (target_lufs_f32, requested_gain_db)
+    } else if analysis.would_clip && !force_clip {
         // Clipping would occur and user didn't force it - adjust to safe level
         let safe_lufs = analysis.max_safe_lufs;
         let safe_gain_db = safe_lufs - current_lufs;
@@ -113,35 +672,65 @@ pub fn normalize_lufs(input: &Path, output: &Path, target_lufs: f64, fade_in: f6
         
         (target_lufs_f32, requested_gain_db)
     };
-    
-    // Apply the calculated gain
-    let gain = db_to_linear(actual_gain_db);
-    for v in &mut audio_data.samples { 
-        *v = (*v * gain).clamp(-1.0, 1.0); 
+
+    Ok(NormalizeReport {
+        measured: current_lufs as f64,
+        requested_target: target_lufs,
+        applied_target: if final_target_lufs == target_lufs_f32 { target_lufs } else { final_target_lufs as f64 },
+        gain_db: actual_gain_db as f64,
+        clipping: Some(analysis),
+    })
+}
+
+/// Analyze the risk of exceeding a true-peak ceiling for LUFS normalization.
+///
+/// Unlike `analyze_clipping_risk`, the headroom is measured from the true peak to the ceiling,
+/// with no extra safety margin: the ceiling is the margin.
+fn analyze_true_peak_risk(audio_data: &AudioData, current_lufs: f32, target_lufs: f32, ceiling_db: f32) -> Result<ClippingAnalysis> {
+    let current_peak_db = audio_data.true_peak_db()? as f32;
+    let headroom_db = ceiling_db - current_peak_db;
+    Ok(ClippingAnalysis {
+        would_clip: target_lufs - current_lufs > headroom_db,
+        max_safe_lufs: current_lufs + headroom_db,
+        current_peak_db,
+        headroom_db,
+    })
+}
+
+/// Write output as raw PCM, in the requested format, or in the one determined by file extension
+fn write_output(output: &Path, audio_data: &AudioData, format: Option<&str>, options: &NormalizeOptions) -> Result<()> {
+    if let Some(sample_format) = options.raw_output {
+        return encode_raw(output, audio_data, sample_format);
     }
 
-    // Apply fades
-    let curve = FadeCurve::from_str(curve);
-    apply_fades(&mut audio_data.samples, audio_data.channels, audio_data.sample_rate, fade_in, fade_out, curve);
+    FormatRegistry::global().encode(output, audio_data, audio_data.bits_per_sample, format)
+}
 
-    // Write output in the format determined by file extension
-    MultiFormatProcessor::write_audio_data(output, &audio_data, 16)?;
-    
-    // Report final results
-    if analysis.would_clip && !force_clip {
-        println!("LUFS normalization completed with safety adjustment:");
-        println!("  requested: {:.2} LUFS -> actual: {:.2} LUFS (gain: {:.2} dB)", 
-                target_lufs, final_target_lufs, actual_gain_db);
-    } else {
-        println!("LUFS normalization completed: {:.2} LUFS (gain: {:.2} dB)", 
-                final_target_lufs, actual_gain_db);
+/// Copy a WAV input verbatim, for when processing left every sample untouched.
+///
+/// Only a cleanly decoded input is copied: with damage repaired or an error policy, strict
+/// mode or a track selected, the file's bytes are not the decoded audio, so it is encoded
+/// like any other output. Returns `true` if the output was written this way.
+fn try_passthrough(input: &Path, output: &Path, audio_data: &AudioData, format: Option<&str>, options: &NormalizeOptions) -> Result<bool> {
+    if options.raw_output.is_some() || options.decode.raw.is_some() || is_stdio(input) || is_stdio(output) {
+        return Ok(false);
     }
-    
-    Ok(())
+    let decode = &options.decode;
+    if !audio_data.damaged_regions.is_empty() || decode.strict || decode.track.is_some() || decode.on_error != DecodeErrorPolicy::default() {
+        return Ok(false);
+    }
+    let encoder = FormatRegistry::global().encoder_for(output, format)?;
+    let is_wav = |p: &Path| p.extension().is_some_and(|e| e.eq_ignore_ascii_case("wav"));
+    if encoder.name() != "wav" || !is_wav(output) || !hound_can_read(input)? {
+        return Ok(false);
+    }
+
+    copy_wav_pcm(input, output)?;
+    Ok(true)
 }
 
 /// Analyze clipping risk for LUFS normalization
-fn analyze_clipping_risk(audio_data: &[f32], current_lufs: f32, target_lufs: f32) -> ClippingAnalysis {
+pub fn analyze_clipping_risk(audio_data: &[f32], current_lufs: f32, target_lufs: f32) -> ClippingAnalysis {
     // Find current peak
     let mut peak = 0.0f32;
     for v in audio_data {
@@ -166,3 +755,209 @@ fn analyze_clipping_risk(audio_data: &[f32], current_lufs: f32, target_lufs: f32
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::temp_dir;
+
+    /// A fresh directory holding `tone.wav`, one second of 440 Hz at -12 dBFS
+    fn dir_with_tone(name: &str) -> PathBuf {
+        let dir = temp_dir(name);
+        let spec = hound::WavSpec { channels: 1, sample_rate: 48000, bits_per_sample: 16, sample_format: hound::SampleFormat::Int };
+        let mut writer = hound::WavWriter::create(dir.join("tone.wav"), spec).unwrap();
+        for i in 0..48000 {
+            let v = (2.0 * std::f64::consts::PI * 440.0 * i as f64 / 48000.0).sin() * 0.25;
+            writer.write_sample((v * 32767.0) as i16).unwrap();
+        }
+        writer.finalize().unwrap();
+        dir
+    }
+
+    fn normalize(input: &Path, output: &Path, options: &NormalizeOptions) -> Result<Vec<OutputReport>> {
+        normalize_to_template(input, &OutputTemplate::literal(output), Target::Peak(-6.0), options)
+    }
+
+    fn with_policy(on_collision: CollisionPolicy) -> NormalizeOptions {
+        NormalizeOptions { on_collision, ..Default::default() }
+    }
+
+    #[test]
+    fn fail_refuses_an_existing_output() {
+        let dir = dir_with_tone("collision-fail");
+        let (input, output) = (dir.join("tone.wav"), dir.join("out.wav"));
+        std::fs::write(&output, b"keep").unwrap();
+        assert!(matches!(normalize(&input, &output, &with_policy(CollisionPolicy::Fail)), Err(Error::Output { .. })));
+        assert_eq!(std::fs::read(&output).unwrap(), b"keep");
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn dry_runs_report_collisions_instead_of_failing() {
+        let dir = dir_with_tone("collision-dry-run");
+        let (input, output) = (dir.join("tone.wav"), dir.join("out.wav"));
+        std::fs::write(&output, b"keep").unwrap();
+        for (on_collision, collision) in [(CollisionPolicy::Fail, Collision::Exists), (CollisionPolicy::Overwrite, Collision::Overwrite)] {
+            let options = NormalizeOptions { on_collision, dry_run: true, ..Default::default() };
+            let reports = normalize(&input, &output, &options).unwrap();
+            assert_eq!((reports[0].path.as_path(), reports[0].collision), (output.as_path(), Some(collision)));
+        }
+        let reports = normalize(&input, &dir.join("new.wav"), &NormalizeOptions { dry_run: true, ..Default::default() }).unwrap();
+        assert_eq!(reports[0].collision, None);
+        assert_eq!(std::fs::read(&output).unwrap(), b"keep");
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn skip_leaves_an_existing_output_alone() {
+        let dir = dir_with_tone("collision-skip");
+        let (input, output) = (dir.join("tone.wav"), dir.join("out.wav"));
+        std::fs::write(&output, b"keep").unwrap();
+        let reports = normalize(&input, &output, &with_policy(CollisionPolicy::Skip)).unwrap();
+        assert!(reports[0].normalize.is_none());
+        assert_eq!(std::fs::read(&output).unwrap(), b"keep");
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn suffix_picks_the_next_free_name() {
+        let dir = dir_with_tone("collision-suffix");
+        let (input, output) = (dir.join("tone.wav"), dir.join("out.wav"));
+        std::fs::write(&output, b"keep").unwrap();
+        std::fs::write(dir.join("out.1.wav"), b"keep").unwrap();
+        let reports = normalize(&input, &output, &with_policy(CollisionPolicy::Suffix)).unwrap();
+        assert_eq!(reports[0].path, dir.join("out.2.wav"));
+        assert!(hound::WavReader::open(dir.join("out.2.wav")).is_ok());
+        assert_eq!(std::fs::read(&output).unwrap(), b"keep");
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn overwrite_replaces_an_existing_output() {
+        let dir = dir_with_tone("collision-overwrite");
+        let (input, output) = (dir.join("tone.wav"), dir.join("out.wav"));
+        std::fs::write(&output, b"old").unwrap();
+        normalize(&input, &output, &with_policy(CollisionPolicy::Overwrite)).unwrap();
+        assert!(hound::WavReader::open(&output).is_ok());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn the_input_is_only_replaced_in_place() {
+        let dir = dir_with_tone("collision-in-place");
+        let input = dir.join("tone.wav");
+        let original = std::fs::read(&input).unwrap();
+        let result = normalize(&input, &input, &with_policy(CollisionPolicy::Overwrite));
+        assert!(matches!(result, Err(Error::Usage(_))));
+        assert_eq!(std::fs::read(&input).unwrap(), original);
+        normalize(&input, &input, &NormalizeOptions { in_place: true, ..Default::default() }).unwrap();
+        assert_ne!(std::fs::read(&input).unwrap(), original);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn a_deleted_output_can_be_written_again() {
+        let dir = dir_with_tone("collision-again");
+        let (input, output) = (dir.join("tone.wav"), dir.join("out.wav"));
+        let options = with_policy(CollisionPolicy::Fail);
+        normalize(&input, &output, &options).unwrap();
+        std::fs::remove_file(&output).unwrap();
+        normalize(&input, &output, &options).unwrap();
+        assert!(output.exists());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn files_sharing_claims_collide_before_anything_is_written() {
+        let dir = dir_with_tone("collision-claims");
+        let (input, output) = (dir.join("tone.wav"), dir.join("out.wav"));
+        let options = NormalizeOptions {
+            on_collision: CollisionPolicy::Suffix,
+            dry_run: true,
+            claims: Some(Arc::default()),
+            ..Default::default()
+        };
+        assert_eq!(normalize(&input, &output, &options).unwrap()[0].path, output);
+        assert_eq!(normalize(&input, &output, &options).unwrap()[0].path, dir.join("out.1.wav"));
+        // Without shared claims, each call only sees the files on disk
+        let alone = NormalizeOptions { claims: None, ..options };
+        assert_eq!(normalize(&input, &output, &alone).unwrap()[0].path, output);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn overwrite_does_not_replace_another_output_of_the_run() {
+        let dir = dir_with_tone("collision-claimed");
+        let (input, output) = (dir.join("tone.wav"), dir.join("out.wav"));
+        std::fs::write(&output, b"old").unwrap();
+        let options = NormalizeOptions { claims: Some(Arc::default()), ..with_policy(CollisionPolicy::Overwrite) };
+        let dry_run = NormalizeOptions { dry_run: true, claims: Some(Arc::default()), ..options.clone() };
+        assert_eq!(normalize(&input, &output, &dry_run).unwrap()[0].collision, Some(Collision::Overwrite));
+        assert_eq!(normalize(&input, &output, &dry_run).unwrap()[0].collision, Some(Collision::Claimed));
+
+        assert_eq!(normalize(&input, &output, &options).unwrap()[0].collision, Some(Collision::Overwrite));
+        let written = std::fs::read(&output).unwrap();
+        assert!(matches!(normalize(&input, &output, &options), Err(Error::Output { .. })));
+        assert_eq!(std::fs::read(&output).unwrap(), written);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn verify_reads_back_the_written_file() {
+        let dir = dir_with_tone("verify");
+        let input = dir.join("tone.wav");
+        let options = NormalizeOptions { verify: Some(0.5), ..Default::default() };
+        let reports = normalize(&input, &dir.join("out.wav"), &options).unwrap();
+        let verification = reports[0].verification.unwrap();
+        assert!(verification.passed() && (verification.intended + 6.0).abs() < 1e-9);
+
+        // WAV data under another extension is decoded as WAV
+        let forced = NormalizeOptions { output_format: Some("wav".to_string()), ..options.clone() };
+        assert!(normalize(&input, &dir.join("out.mp3"), &forced).unwrap()[0].verification.unwrap().passed());
+
+        // No encoder, nothing written and nothing to verify: an output error, not an input one
+        assert!(matches!(normalize(&input, &dir.join("out.flac"), &options), Err(Error::Output { .. })));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn unity_gain_on_a_truncated_wav_writes_the_decoded_audio() {
+        let dir = dir_with_tone("truncated-unity");
+        let input = dir.join("tone.wav");
+        // 20000 whole frames and half of the next one
+        std::fs::OpenOptions::new().write(true).open(&input).unwrap().set_len(44 + 40001).unwrap();
+        let peak = MultiFormatProcessor::decode_audio_with_options(&input, &DecodeOptions::default()).unwrap().peak_db();
+
+        for (on_error, frames) in [(DecodeErrorPolicy::Silence, 48000), (DecodeErrorPolicy::Skip, 20000)] {
+            let output = dir.join("out.wav");
+            let options = NormalizeOptions { decode: DecodeOptions { on_error, ..Default::default() }, ..Default::default() };
+            let reports = normalize_to_template(&input, &OutputTemplate::literal(&output), Target::Peak(peak), &options).unwrap();
+            assert!(reports[0].normalize.unwrap().gain_db.abs() < 1e-6);
+            assert_eq!(hound::WavReader::open(&output).unwrap().duration(), frames);
+            std::fs::remove_file(&output).unwrap();
+        }
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn unimplemented_output_formats_write_wav_under_its_own_name() {
+        let dir = dir_with_tone("unsupported-output");
+        let input = dir.join("tone.wav");
+        let reports = normalize(&input, &dir.join("out.flac"), &NormalizeOptions::default()).unwrap();
+        assert_eq!(reports[0].path, dir.join("out.wav"));
+        assert_eq!(hound::WavReader::open(dir.join("out.wav")).unwrap().spec().bits_per_sample, 24);
+
+        // The written name is the one checked for collisions
+        let result = normalize(&input, &dir.join("out.mp3"), &NormalizeOptions::default());
+        assert!(matches!(result, Err(Error::Output { .. })), "{:?}", result);
+        let options = NormalizeOptions { on_collision: CollisionPolicy::Suffix, verify: Some(0.5), ..Default::default() };
+        let reports = normalize(&input, &dir.join("out.mp3"), &options).unwrap();
+        assert_eq!(reports[0].path, dir.join("out.1.wav"));
+        assert!(reports[0].verification.is_some());
+
+        let mut files: Vec<_> = std::fs::read_dir(&dir).unwrap().map(|e| e.unwrap().file_name()).collect();
+        files.sort();
+        assert_eq!(files, ["out.1.wav", "out.wav", "tone.wav"]);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,179 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use serde::{Deserialize, Serialize};
+use crate::error::{Error, Result};
+use crate::normalizer::{NormalizeOptions, Target};
+use crate::processor::StageSpec;
+
+/// A named delivery target: loudness, true-peak ceiling and how peaks are kept under it
+///
+/// The ceiling is approximate when the limiter holds it: the limiter works on sample peaks,
+/// `headroom` below the ceiling, and inter-sample peaks larger than the headroom still exceed it.
+/// Use `--verify` or `check` to measure the true peak of the result.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Preset {
+    /// Filled in from the table key for presets read from a config file
+    #[serde(skip_deserializing)]
+    pub name: String,
+    /// Integrated loudness target in LUFS
+    pub lufs: f64,
+    /// Highest true peak in dBTP
+    #[serde(default = "default_true_peak")]
+    pub true_peak: f64,
+    /// Add a limiter after normalization (when no `--chain` is given) instead of lowering the gain
+    #[serde(default = "default_limiter")]
+    pub limiter: bool,
+    /// How far below the true-peak ceiling the sample-peak limiter sits, in dB, to absorb inter-sample peaks
+    #[serde(default = "default_headroom")]
+    pub headroom: f64,
+    #[serde(default)]
+    pub description: String,
+}
+
+fn default_true_peak() -> f64 {
+    -1.0
+}
+
+fn default_limiter() -> bool {
+    true
+}
+
+fn default_headroom() -> f64 {
+    0.5
+}
+
+impl Preset {
+    fn builtin(name: &str, lufs: f64, true_peak: f64, description: &str) -> Self {
+        Preset {
+            name: name.to_string(),
+            lufs,
+            true_peak,
+            limiter: true,
+            headroom: default_headroom(),
+            description: description.to_string(),
+        }
+    }
+
+    /// The presets shipped with the tool
+    pub fn builtins() -> Vec<Preset> {
+        vec![
+            Preset::builtin("ebu-r128", -23.0, -1.0, "EBU R128 broadcast"),
+            Preset::builtin("atsc-a85", -24.0, -2.0, "ATSC A/85 US broadcast"),
+            Preset::builtin("spotify", -14.0, -1.0, "Spotify and other -14 LUFS streaming services"),
+            Preset::builtin("apple-music", -16.0, -1.0, "Apple Music Sound Check"),
+            Preset::builtin("podcast", -16.0, -1.0, "Podcasts (Apple Podcasts and most hosts)"),
+            // ACX measures RMS (-23 to -18 dBFS) and sample peaks (<= -3 dBFS); -20 LUFS lands in that range for speech
+            Preset::builtin("audiobook-acx", -20.0, -3.0, "ACX / Audible audiobooks"),
+            Preset::builtin("netflix-dialog", -27.0, -2.0, "Netflix dialogue-gated delivery"),
+        ]
+    }
+
+    /// Look up a preset by name; presets from the config file take precedence over built-in ones
+    pub fn find(name: &str, custom: &BTreeMap<String, Preset>) -> Result<Preset> {
+        if let Some(preset) = custom.get(name) {
+            return Ok(preset.clone());
+        }
+        Preset::builtins().into_iter().find(|p| p.name == name).ok_or_else(|| {
+            let mut names: Vec<String> = Preset::builtins().into_iter().map(|p| p.name).collect();
+            names.extend(custom.keys().cloned());
+            names.sort();
+            names.dedup();
+            Error::Usage(format!("unknown preset '{}' (available: {})", name, names.join(", ")))
+        })
+    }
+
+    /// Apply the ceiling and limiter defaults to `options` and return the loudness target.
+    /// An explicit `--chain` is kept as given.
+    pub fn apply(&self, options: &mut NormalizeOptions) -> Target {
+        options.true_peak_ceiling = Some(self.true_peak);
+        if self.limiter && options.chain.is_empty() {
+            options.chain = vec![
+                StageSpec::Normalize,
+                StageSpec::Limiter { ceiling_db: self.true_peak - self.headroom },
+                StageSpec::Fade,
+            ];
+        }
+        Target::Lufs(self.lufs)
+    }
+}
+
+impl fmt::Display for Preset {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:.1} LUFS, true peak <= {:.1} dBTP", self.lufs, self.true_peak)?;
+        if self.limiter {
+            write!(f, ", limiter at {:.1} dBFS", self.true_peak - self.headroom)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn custom(name: &str, toml: &str) -> BTreeMap<String, Preset> {
+        let mut preset: Preset = toml::from_str(toml).unwrap();
+        preset.name = name.to_string();
+        BTreeMap::from([(name.to_string(), preset)])
+    }
+
+    #[test]
+    fn builtins_have_unique_names_and_limit_below_their_ceiling() {
+        let builtins = Preset::builtins();
+        let mut names: Vec<_> = builtins.iter().map(|p| p.name.as_str()).collect();
+        names.sort();
+        names.dedup();
+        assert_eq!(names.len(), builtins.len());
+        assert!(builtins.iter().all(|p| p.limiter && p.headroom > 0.0 && p.true_peak < 0.0));
+    }
+
+    #[test]
+    fn finds_builtins_and_prefers_custom_presets() {
+        let none = BTreeMap::new();
+        assert_eq!(Preset::find("ebu-r128", &none).unwrap().lufs, -23.0);
+
+        let custom = custom("ebu-r128", "lufs = -24.0");
+        let preset = Preset::find("ebu-r128", &custom).unwrap();
+        assert_eq!((preset.lufs, preset.true_peak, preset.limiter, preset.headroom), (-24.0, -1.0, true, 0.5));
+    }
+
+    #[test]
+    fn unknown_presets_list_the_available_ones() {
+        let custom = custom("house", "lufs = -18.0");
+        let Err(Error::Usage(message)) = Preset::find("nope", &custom) else { panic!("expected a usage error") };
+        assert!(message.contains("'nope'") && message.contains("house") && message.contains("spotify"), "{}", message);
+    }
+
+    #[test]
+    fn custom_presets_reject_unknown_keys() {
+        assert!(toml::from_str::<Preset>("lufs = -16.0\nceiling = -1.0").is_err());
+        assert!(toml::from_str::<Preset>("true_peak = -1.0").is_err());
+    }
+
+    #[test]
+    fn apply_sets_the_ceiling_and_limiter_chain() {
+        let preset = Preset::find("atsc-a85", &BTreeMap::new()).unwrap();
+        let mut options = NormalizeOptions::default();
+        assert_eq!(preset.apply(&mut options), Target::Lufs(-24.0));
+        assert_eq!(options.true_peak_ceiling, Some(-2.0));
+        assert_eq!(
+            options.chain,
+            [StageSpec::Normalize, StageSpec::Limiter { ceiling_db: -2.5 }, StageSpec::Fade]
+        );
+    }
+
+    #[test]
+    fn apply_keeps_an_explicit_chain() {
+        let preset = Preset::find("spotify", &BTreeMap::new()).unwrap();
+        let chain = vec![StageSpec::HighPass { cutoff_hz: 80.0 }, StageSpec::Normalize];
+        let mut options = NormalizeOptions { chain: chain.clone(), ..Default::default() };
+        preset.apply(&mut options);
+        assert_eq!((options.chain, options.true_peak_ceiling), (chain, Some(-1.0)));
+
+        let unlimited = custom("quiet", "lufs = -20.0\nlimiter = false");
+        let mut options = NormalizeOptions::default();
+        unlimited["quiet"].apply(&mut options);
+        assert!(options.chain.is_empty());
+    }
+}
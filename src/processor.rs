@@ -0,0 +1,424 @@
+use std::fmt;
+use crate::error::{Error, Result};
+use crate::multi_format_processor::{AudioData, DamagedRegion};
+use crate::normalizer::NormalizeReport;
+
+/// An effect applied to a whole decoded buffer
+pub trait Processor: Send {
+    /// Stage name as used on the command line
+    fn name(&self) -> &'static str;
+
+    fn process(&mut self, audio_data: &mut AudioData) -> Result<()>;
+
+    /// Whether the last `process` call left every sample untouched
+    fn is_transparent(&self) -> bool {
+        false
+    }
+
+    /// Measurement and gain of the last `process` call, for normalizing stages
+    fn report(&self) -> Option<NormalizeReport> {
+        None
+    }
+}
+
+/// An ordered list of processors run one after the other
+#[derive(Default)]
+pub struct Chain {
+    stages: Vec<Box<dyn Processor>>,
+}
+
+impl Chain {
+    pub fn builder() -> ChainBuilder {
+        ChainBuilder::default()
+    }
+
+    /// Run every stage in order, returning the report of the last normalizing stage
+    pub fn process(&mut self, audio_data: &mut AudioData) -> Result<Option<NormalizeReport>> {
+        for stage in &mut self.stages {
+            stage.process(audio_data)?;
+        }
+        Ok(self.stages.iter().rev().find_map(|s| s.report()))
+    }
+
+    /// Whether the last `process` call left every sample untouched
+    pub fn is_transparent(&self) -> bool {
+        self.stages.iter().all(|s| s.is_transparent())
+    }
+
+    pub fn names(&self) -> Vec<&'static str> {
+        self.stages.iter().map(|s| s.name()).collect()
+    }
+}
+
+#[derive(Default)]
+pub struct ChainBuilder {
+    stages: Vec<Box<dyn Processor>>,
+}
+
+impl ChainBuilder {
+    /// Append a stage to the end of the chain
+    pub fn stage(mut self, processor: impl Processor + 'static) -> Self {
+        self.stages.push(Box::new(processor));
+        self
+    }
+
+    pub fn build(self) -> Chain {
+        Chain { stages: self.stages }
+    }
+}
+
+/// A stage of the command-line chain, written `name[:parameter]`.
+///
+/// `normalize` and `fade` take their settings from the normalization options.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StageSpec {
+    /// Remove leading and trailing frames quieter than the threshold (dBFS, default -60)
+    Trim { threshold_db: f64 },
+    /// Second-order high-pass filter (Hz, default 80)
+    HighPass { cutoff_hz: f64 },
+    /// Fixed gain (dB)
+    Gain { db: f64 },
+    /// Peak or LUFS normalization to the requested target
+    Normalize,
+    /// Peak limiter (ceiling in dBFS, default -1)
+    Limiter { ceiling_db: f64 },
+    /// Fade in and out
+    Fade,
+}
+
+impl StageSpec {
+    /// The chain used when none is given: normalize, then fade
+    pub const DEFAULT_CHAIN: [StageSpec; 2] = [StageSpec::Normalize, StageSpec::Fade];
+}
+
+impl std::str::FromStr for StageSpec {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let (name, param) = match s.split_once(':') {
+            Some((name, param)) => (name, Some(param)),
+            None => (s, None),
+        };
+        let value = |default: Option<f64>| match param {
+            Some(p) => p.trim().parse::<f64>().map_err(|_| format!("invalid parameter '{}' for stage '{}'", p, name)),
+            None => default.ok_or_else(|| format!("stage '{}' needs a parameter, e.g. '{}:3'", name, name)),
+        };
+        let no_param = |stage: StageSpec| match param {
+            Some(_) => Err(format!("stage '{}' takes no parameter", name)),
+            None => Ok(stage),
+        };
+
+        match name.trim().to_lowercase().as_str() {
+            "trim" => Ok(StageSpec::Trim { threshold_db: value(Some(-60.0))? }),
+            "highpass" | "high-pass" | "hpf" => Ok(StageSpec::HighPass { cutoff_hz: value(Some(80.0))? }),
+            "gain" => Ok(StageSpec::Gain { db: value(None)? }),
+            "normalize" => no_param(StageSpec::Normalize),
+            "limiter" | "limit" => Ok(StageSpec::Limiter { ceiling_db: value(Some(-1.0))? }),
+            "fade" => no_param(StageSpec::Fade),
+            other => Err(format!(
+                "unknown stage '{}' (expected trim, highpass, gain, normalize, limiter or fade)",
+                other
+            )),
+        }
+    }
+}
+
+impl fmt::Display for StageSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StageSpec::Trim { threshold_db } => write!(f, "trim:{}", threshold_db),
+            StageSpec::HighPass { cutoff_hz } => write!(f, "highpass:{}", cutoff_hz),
+            StageSpec::Gain { db } => write!(f, "gain:{}", db),
+            StageSpec::Normalize => write!(f, "normalize"),
+            StageSpec::Limiter { ceiling_db } => write!(f, "limiter:{}", ceiling_db),
+            StageSpec::Fade => write!(f, "fade"),
+        }
+    }
+}
+
+fn db_to_linear(db: f64) -> f64 {
+    10f64.powf(db / 20.0)
+}
+
+/// Remove leading and trailing silence below a threshold
+#[derive(Debug, Clone)]
+pub struct Trim {
+    pub threshold_db: f64,
+    trimmed: bool,
+}
+
+impl Trim {
+    pub fn new(threshold_db: f64) -> Self {
+        Trim { threshold_db, trimmed: false }
+    }
+}
+
+impl Processor for Trim {
+    fn name(&self) -> &'static str {
+        "trim"
+    }
+
+    fn process(&mut self, audio_data: &mut AudioData) -> Result<()> {
+        let channels = audio_data.channels.max(1);
+        let threshold = db_to_linear(self.threshold_db) as f32;
+        let loud = |frame: &[f32]| frame.iter().any(|s| s.abs() >= threshold);
+
+        let frames: Vec<&[f32]> = audio_data.samples.chunks_exact(channels).collect();
+        let start = frames.iter().position(|f| loud(f)).unwrap_or(frames.len());
+        let end = frames.iter().rposition(|f| loud(f)).map_or(start, |i| i + 1);
+        let total = frames.len();
+
+        self.trimmed = start > 0 || end < total;
+        if !self.trimmed {
+            return Ok(());
+        }
+
+        audio_data.samples.truncate(end * channels);
+        audio_data.samples.drain(..start * channels);
+
+        // Keep damaged regions pointing at the same audio
+        let (start, end) = (start as u64, end as u64);
+        audio_data.damaged_regions = audio_data
+            .damaged_regions
+            .iter()
+            .filter_map(|r| {
+                let from = r.start_frame.clamp(start, end);
+                let to = (r.start_frame + r.frames).clamp(start, end);
+                (to > from).then_some(DamagedRegion { start_frame: from - start, frames: to - from })
+            })
+            .collect();
+        Ok(())
+    }
+
+    fn is_transparent(&self) -> bool {
+        !self.trimmed
+    }
+}
+
+/// Second-order Butterworth high-pass filter
+#[derive(Debug, Clone)]
+pub struct HighPass {
+    pub cutoff_hz: f64,
+}
+
+impl HighPass {
+    pub fn new(cutoff_hz: f64) -> Self {
+        HighPass { cutoff_hz }
+    }
+}
+
+impl Processor for HighPass {
+    fn name(&self) -> &'static str {
+        "highpass"
+    }
+
+    fn process(&mut self, audio_data: &mut AudioData) -> Result<()> {
+        let rate = audio_data.sample_rate as f64;
+        if self.cutoff_hz <= 0.0 || self.cutoff_hz >= rate / 2.0 {
+            return Err(Error::Usage(format!(
+                "high-pass cutoff {} Hz must be between 0 and {} Hz", self.cutoff_hz, rate / 2.0
+            )));
+        }
+
+        // Biquad coefficients from the Audio EQ Cookbook, Q = 1/sqrt(2)
+        let w0 = 2.0 * std::f64::consts::PI * self.cutoff_hz / rate;
+        let alpha = w0.sin() / std::f64::consts::SQRT_2;
+        let cos = w0.cos();
+        let a0 = 1.0 + alpha;
+        let (b0, b1, b2) = ((1.0 + cos) / 2.0 / a0, -(1.0 + cos) / a0, (1.0 + cos) / 2.0 / a0);
+        let (a1, a2) = (-2.0 * cos / a0, (1.0 - alpha) / a0);
+
+        let channels = audio_data.channels.max(1);
+        // Per channel: x[n-1], x[n-2], y[n-1], y[n-2]
+        let mut state = vec![[0.0f64; 4]; channels];
+        for frame in audio_data.samples.chunks_exact_mut(channels) {
+            for (sample, s) in frame.iter_mut().zip(&mut state) {
+                let x = *sample as f64;
+                let y = b0 * x + b1 * s[0] + b2 * s[1] - a1 * s[2] - a2 * s[3];
+                *s = [x, s[0], y, s[2]];
+                *sample = y as f32;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Fixed gain
+#[derive(Debug, Clone)]
+pub struct Gain {
+    pub db: f64,
+}
+
+impl Gain {
+    pub fn new(db: f64) -> Self {
+        Gain { db }
+    }
+}
+
+impl Processor for Gain {
+    fn name(&self) -> &'static str {
+        "gain"
+    }
+
+    fn process(&mut self, audio_data: &mut AudioData) -> Result<()> {
+        if self.db != 0.0 {
+            let gain = db_to_linear(self.db) as f32;
+            for v in &mut audio_data.samples { *v *= gain; }
+        }
+        Ok(())
+    }
+
+    fn is_transparent(&self) -> bool {
+        self.db == 0.0
+    }
+}
+
+/// Peak limiter with instant attack and exponential release, so no sample exceeds the ceiling
+///
+/// The limiter is not oversampled: it holds sample peaks, and the true peak between samples
+/// may still be above the ceiling.
+#[derive(Debug, Clone)]
+pub struct Limiter {
+    pub ceiling_db: f64,
+    pub release_ms: f64,
+    engaged: bool,
+}
+
+impl Limiter {
+    pub fn new(ceiling_db: f64) -> Self {
+        Limiter { ceiling_db, release_ms: 50.0, engaged: false }
+    }
+}
+
+impl Processor for Limiter {
+    fn name(&self) -> &'static str {
+        "limiter"
+    }
+
+    fn process(&mut self, audio_data: &mut AudioData) -> Result<()> {
+        let ceiling = db_to_linear(self.ceiling_db) as f32;
+        let release_frames = self.release_ms / 1000.0 * audio_data.sample_rate as f64;
+        let release = if release_frames > 0.0 { (-1.0 / release_frames).exp() as f32 } else { 0.0 };
+
+        let mut envelope = 1.0f32;
+        self.engaged = false;
+        for frame in audio_data.samples.chunks_exact_mut(audio_data.channels.max(1)) {
+            let peak = frame.iter().fold(0.0f32, |p, s| p.max(s.abs()));
+            let wanted = if peak > ceiling { ceiling / peak } else { 1.0 };
+            // Duck immediately, recover smoothly; the envelope never exceeds the wanted gain
+            envelope = if wanted < envelope { wanted } else { wanted - (wanted - envelope) * release };
+            if envelope < 1.0 {
+                self.engaged = true;
+                // Clamp away the rounding of `ceiling / peak * s`, which can land one ulp above
+                for s in frame.iter_mut() { *s = (*s * envelope).clamp(-ceiling, ceiling); }
+            }
+        }
+        Ok(())
+    }
+
+    fn is_transparent(&self) -> bool {
+        !self.engaged
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn audio(samples: Vec<f32>, channels: usize) -> AudioData {
+        AudioData { samples, channels, sample_rate: 48000, bits_per_sample: 16, is_float: false, damaged_regions: Vec::new() }
+    }
+
+    fn sine(hz: f64, frames: usize) -> Vec<f32> {
+        (0..frames).map(|i| (2.0 * std::f64::consts::PI * hz * i as f64 / 48000.0).sin() as f32 * 0.5).collect()
+    }
+
+    fn rms(samples: &[f32]) -> f64 {
+        (samples.iter().map(|&s| (s as f64).powi(2)).sum::<f64>() / samples.len() as f64).sqrt()
+    }
+
+    #[test]
+    fn limiter_keeps_every_sample_under_the_ceiling() {
+        let mut data = audio(sine(440.0, 4800).into_iter().map(|s| s * 3.0).collect(), 1);
+        let mut limiter = Limiter::new(-1.0);
+        limiter.process(&mut data).unwrap();
+        let ceiling = db_to_linear(-1.0) as f32;
+        let peak = data.samples.iter().fold(0.0f32, |p, s| p.max(s.abs()));
+        assert!(peak <= ceiling, "peak {} above ceiling {}", peak, ceiling);
+        assert!(!limiter.is_transparent());
+    }
+
+    #[test]
+    fn limiter_leaves_quiet_audio_untouched() {
+        let original = sine(440.0, 4800);
+        let mut data = audio(original.clone(), 1);
+        let mut limiter = Limiter::new(-1.0);
+        limiter.process(&mut data).unwrap();
+        assert_eq!(data.samples, original);
+        assert!(limiter.is_transparent());
+    }
+
+    #[test]
+    fn limiter_releases_after_a_peak() {
+        let mut samples = vec![0.5f32; 48000];
+        samples[100] = 1.0;
+        let mut data = audio(samples, 1);
+        Limiter::new(-6.0).process(&mut data).unwrap();
+        // Ducked right after the peak, back to the full level of 0.5 (just under -6 dBFS) a second later
+        assert!(data.samples[101] < 0.5);
+        assert!((data.samples[47999] - 0.5).abs() < 1e-3);
+    }
+
+    #[test]
+    fn trim_removes_quiet_frames_at_both_ends_and_moves_damaged_regions() {
+        let mut samples = vec![0.0f32; 20];
+        samples[6..14].fill(0.5);
+        let mut data = audio(samples, 2);
+        data.damaged_regions = vec![DamagedRegion { start_frame: 0, frames: 4 }, DamagedRegion { start_frame: 5, frames: 1 }];
+        let mut trim = Trim::new(-60.0);
+        trim.process(&mut data).unwrap();
+        assert_eq!(data.samples, vec![0.5; 8]);
+        assert_eq!(data.damaged_regions, vec![DamagedRegion { start_frame: 0, frames: 1 }, DamagedRegion { start_frame: 2, frames: 1 }]);
+        assert!(!trim.is_transparent());
+    }
+
+    #[test]
+    fn trim_is_transparent_without_silence() {
+        let mut data = audio(vec![0.5; 8], 2);
+        let mut trim = Trim::new(-60.0);
+        trim.process(&mut data).unwrap();
+        assert_eq!(data.samples.len(), 8);
+        assert!(trim.is_transparent());
+    }
+
+    #[test]
+    fn high_pass_attenuates_below_the_cutoff_and_passes_above() {
+        let mut low = audio(sine(20.0, 48000), 1);
+        let mut high = audio(sine(2000.0, 48000), 1);
+        HighPass::new(200.0).process(&mut low).unwrap();
+        HighPass::new(200.0).process(&mut high).unwrap();
+        let reference = rms(&sine(20.0, 48000)[24000..]);
+        // Second order: 12 dB per octave, so about -40 dB a decade below the cutoff
+        assert!(20.0 * (rms(&low.samples[24000..]) / reference).log10() < -35.0);
+        assert!(20.0 * (rms(&high.samples[24000..]) / reference).log10() > -0.1);
+    }
+
+    #[test]
+    fn high_pass_rejects_a_cutoff_above_nyquist() {
+        let mut data = audio(sine(440.0, 480), 1);
+        assert!(matches!(HighPass::new(30000.0).process(&mut data), Err(Error::Usage(_))));
+    }
+
+    #[test]
+    fn stage_specs_parse_with_defaults_and_round_trip() {
+        assert_eq!("trim".parse(), Ok(StageSpec::Trim { threshold_db: -60.0 }));
+        assert_eq!("hpf:120".parse(), Ok(StageSpec::HighPass { cutoff_hz: 120.0 }));
+        assert_eq!("limiter".parse(), Ok(StageSpec::Limiter { ceiling_db: -1.0 }));
+        assert!("gain".parse::<StageSpec>().is_err());
+        assert!("fade:2".parse::<StageSpec>().is_err());
+        assert!("reverb".parse::<StageSpec>().is_err());
+        for spec in [StageSpec::Gain { db: -3.5 }, StageSpec::Normalize, StageSpec::Limiter { ceiling_db: -2.0 }] {
+            assert_eq!(spec.to_string().parse(), Ok(spec));
+        }
+    }
+}
@@ -0,0 +1,185 @@
+use std::fmt;
+use std::path::Path;
+use tracing::field::{Field, Visit};
+use tracing::{Event, Subscriber};
+use tracing_subscriber::layer::{Context, Layer};
+
+/// `tracing` target of the decode progress events, emitted at trace level
+pub const PROGRESS_TARGET: &str = "audio_normalizer::progress";
+
+/// How far the decoding of one input has got
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProgressEvent {
+    pub input: String,
+    /// Frames decoded so far
+    pub frames: u64,
+    /// Frames in the track, when the container says
+    pub total_frames: Option<u64>,
+}
+
+impl ProgressEvent {
+    /// Fraction decoded, from 0 to 1, when the total is known
+    pub fn fraction(&self) -> Option<f64> {
+        self.total_frames
+            .filter(|&total| total > 0)
+            .map(|total| (self.frames as f64 / total as f64).min(1.0))
+    }
+}
+
+/// A `tracing` layer that turns progress events into calls to `on_progress`.
+///
+/// Add it with a filter that lets `PROGRESS_TARGET` through at trace level; without a
+/// subscriber interested in them, emitting progress events costs next to nothing.
+pub struct ProgressLayer<F> {
+    on_progress: F,
+}
+
+impl<F: Fn(ProgressEvent) + Send + Sync + 'static> ProgressLayer<F> {
+    pub fn new(on_progress: F) -> Self {
+        ProgressLayer { on_progress }
+    }
+}
+
+impl<S: Subscriber, F: Fn(ProgressEvent) + Send + Sync + 'static> Layer<S> for ProgressLayer<F> {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        if event.metadata().target() != PROGRESS_TARGET {
+            return;
+        }
+        let mut visitor = ProgressVisitor::default();
+        event.record(&mut visitor);
+        if let (Some(input), Some(frames)) = (visitor.input, visitor.frames) {
+            (self.on_progress)(ProgressEvent { input, frames, total_frames: visitor.total_frames });
+        }
+    }
+}
+
+#[derive(Default)]
+struct ProgressVisitor {
+    input: Option<String>,
+    frames: Option<u64>,
+    total_frames: Option<u64>,
+}
+
+impl Visit for ProgressVisitor {
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        match field.name() {
+            "frames" => self.frames = Some(value),
+            "total_frames" => self.total_frames = Some(value),
+            _ => {}
+        }
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "input" {
+            self.input = Some(value.to_string());
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name() == "input" {
+            self.input = Some(format!("{:?}", value));
+        }
+    }
+}
+
+/// Emits a progress event for each percent decoded, or every 2^20 frames when the total is unknown
+pub(crate) struct DecodeProgress {
+    input: String,
+    total_frames: Option<u64>,
+    frames: u64,
+    next_report: u64,
+}
+
+impl DecodeProgress {
+    pub(crate) fn new(input: &Path, total_frames: Option<u64>) -> Self {
+        let mut progress = DecodeProgress {
+            input: input.display().to_string(),
+            total_frames: total_frames.filter(|&total| total > 0),
+            frames: 0,
+            next_report: 0,
+        };
+        progress.report();
+        progress
+    }
+
+    fn step(&self) -> u64 {
+        self.total_frames.map_or(1 << 20, |total| (total / 100).max(1))
+    }
+
+    pub(crate) fn advance(&mut self, frames: u64) {
+        self.frames += frames;
+        if self.frames >= self.next_report {
+            self.report();
+        }
+    }
+
+    fn report(&mut self) {
+        tracing::trace!(
+            target: PROGRESS_TARGET,
+            input = self.input.as_str(),
+            frames = self.frames,
+            total_frames = self.total_frames,
+            "decoded"
+        );
+        self.next_report = self.frames + self.step();
+    }
+
+    /// Report the final count, which may differ from the container's total
+    pub(crate) fn finish(mut self) {
+        self.total_frames = Some(self.frames);
+        self.report();
+    }
+}
+
+/// Run `f` with a `ProgressLayer` collecting the progress events it emits on this thread
+#[cfg(test)]
+pub(crate) fn capture_progress<R>(f: impl FnOnce() -> R) -> (R, Vec<ProgressEvent>) {
+    use std::sync::{Arc, Mutex};
+    use tracing_subscriber::filter::{LevelFilter, Targets};
+    use tracing_subscriber::prelude::*;
+
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let sink = Arc::clone(&events);
+    let layer = ProgressLayer::new(move |event| sink.lock().unwrap().push(event))
+        .with_filter(Targets::new().with_target(PROGRESS_TARGET, LevelFilter::TRACE));
+    let result = tracing::subscriber::with_default(tracing_subscriber::registry().with(layer), f);
+    let events = std::mem::take(&mut *events.lock().unwrap());
+    (result, events)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn progress_events_reach_the_sink() {
+        let ((), events) = capture_progress(|| {
+            tracing::trace!(target: PROGRESS_TARGET, input = "a.wav", frames = 5u64, total_frames = 10u64, "decoded");
+            tracing::trace!(target: PROGRESS_TARGET, input = %Path::new("b.wav").display(), frames = 7u64, "decoded");
+            // Other events, and progress events missing a field, are not progress
+            tracing::info!(input = "c.wav", frames = 1u64, "decoded");
+            tracing::trace!(target: PROGRESS_TARGET, input = "d.wav", "decoded");
+        });
+        assert_eq!(events, [
+            ProgressEvent { input: "a.wav".to_string(), frames: 5, total_frames: Some(10) },
+            ProgressEvent { input: "b.wav".to_string(), frames: 7, total_frames: None },
+        ]);
+        assert_eq!(events[0].fraction(), Some(0.5));
+        assert_eq!(events[1].fraction(), None);
+    }
+
+    #[test]
+    fn decode_progress_reports_each_percent_and_the_final_count() {
+        let ((), events) = capture_progress(|| {
+            let mut progress = DecodeProgress::new(Path::new("a.wav"), Some(1000));
+            for _ in 0..30 {
+                progress.advance(3);
+            }
+            progress.finish();
+        });
+        let frames: Vec<u64> = events.iter().map(|e| e.frames).collect();
+        assert_eq!(frames, [0, 12, 24, 36, 48, 60, 72, 84, 90]);
+        // The final count replaces the container's total
+        assert_eq!(events.last().unwrap().fraction(), Some(1.0));
+    }
+}
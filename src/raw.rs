@@ -0,0 +1,368 @@
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use crate::atomic::write_atomically;
+use crate::audio_processor::{f32_to_int, int_to_f32};
+use crate::error::{Error, Result};
+use crate::interrupt;
+use crate::multi_format_processor::{is_stdio, AudioData, PROGRESS_CHUNK_FRAMES};
+use crate::progress::DecodeProgress;
+use tracing::warn;
+
+/// Sample encoding of headerless PCM
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum RawSampleFormat {
+    /// Signed 8-bit
+    S8,
+    /// Unsigned 8-bit (offset 128)
+    U8,
+    /// Signed 16-bit little-endian
+    S16le,
+    /// Signed 16-bit big-endian
+    S16be,
+    /// Signed 24-bit little-endian, packed in 3 bytes
+    S24le,
+    /// Signed 32-bit little-endian
+    S32le,
+    /// 32-bit float little-endian
+    F32le,
+    /// 64-bit float little-endian
+    F64le,
+    /// G.711 A-law
+    Alaw,
+    /// G.711 µ-law
+    Mulaw,
+}
+
+impl RawSampleFormat {
+    pub fn bytes_per_sample(self) -> usize {
+        match self {
+            RawSampleFormat::S8 | RawSampleFormat::U8 | RawSampleFormat::Alaw | RawSampleFormat::Mulaw => 1,
+            RawSampleFormat::S16le | RawSampleFormat::S16be => 2,
+            RawSampleFormat::S24le => 3,
+            RawSampleFormat::S32le | RawSampleFormat::F32le => 4,
+            RawSampleFormat::F64le => 8,
+        }
+    }
+
+    /// Bit depth recorded in decoded `AudioData`; companded formats expand to 16-bit linear
+    /// and 64-bit float is kept as 32-bit float
+    pub fn bits_per_sample(self) -> u16 {
+        match self {
+            RawSampleFormat::S8 | RawSampleFormat::U8 => 8,
+            RawSampleFormat::S16le | RawSampleFormat::S16be | RawSampleFormat::Alaw | RawSampleFormat::Mulaw => 16,
+            RawSampleFormat::S24le => 24,
+            RawSampleFormat::S32le | RawSampleFormat::F32le | RawSampleFormat::F64le => 32,
+        }
+    }
+
+    pub fn is_float(self) -> bool {
+        matches!(self, RawSampleFormat::F32le | RawSampleFormat::F64le)
+    }
+
+    fn decode_sample(self, b: &[u8]) -> f32 {
+        match self {
+            RawSampleFormat::S8 => int_to_f32(b[0] as i8 as i32, 8),
+            RawSampleFormat::U8 => int_to_f32(b[0] as i32 - 128, 8),
+            RawSampleFormat::S16le => int_to_f32(i16::from_le_bytes([b[0], b[1]]) as i32, 16),
+            RawSampleFormat::S16be => int_to_f32(i16::from_be_bytes([b[0], b[1]]) as i32, 16),
+            // Place the 24 bits at the top of an i32 and shift back to sign-extend
+            RawSampleFormat::S24le => int_to_f32(i32::from_le_bytes([0, b[0], b[1], b[2]]) >> 8, 24),
+            RawSampleFormat::S32le => int_to_f32(i32::from_le_bytes([b[0], b[1], b[2], b[3]]), 32),
+            RawSampleFormat::F32le => f32::from_le_bytes([b[0], b[1], b[2], b[3]]),
+            RawSampleFormat::F64le => f64::from_le_bytes([b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7]]) as f32,
+            RawSampleFormat::Alaw => int_to_f32(alaw_to_linear(b[0]) as i32, 16),
+            RawSampleFormat::Mulaw => int_to_f32(mulaw_to_linear(b[0]) as i32, 16),
+        }
+    }
+
+    fn encode_sample(self, v: f32, out: &mut Vec<u8>) {
+        match self {
+            RawSampleFormat::S8 => out.push(f32_to_int(v, 8) as i8 as u8),
+            RawSampleFormat::U8 => out.push((f32_to_int(v, 8) + 128) as u8),
+            RawSampleFormat::S16le => out.extend_from_slice(&(f32_to_int(v, 16) as i16).to_le_bytes()),
+            RawSampleFormat::S16be => out.extend_from_slice(&(f32_to_int(v, 16) as i16).to_be_bytes()),
+            RawSampleFormat::S24le => out.extend_from_slice(&f32_to_int(v, 24).to_le_bytes()[..3]),
+            RawSampleFormat::S32le => out.extend_from_slice(&f32_to_int(v, 32).to_le_bytes()),
+            RawSampleFormat::F32le => out.extend_from_slice(&v.clamp(-1.0, 1.0).to_le_bytes()),
+            RawSampleFormat::F64le => out.extend_from_slice(&(v.clamp(-1.0, 1.0) as f64).to_le_bytes()),
+            RawSampleFormat::Alaw => out.push(linear_to_alaw(f32_to_int(v, 16) as i16)),
+            RawSampleFormat::Mulaw => out.push(linear_to_mulaw(f32_to_int(v, 16) as i16)),
+        }
+    }
+}
+
+/// Layout of headerless PCM input
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RawFormat {
+    pub sample_format: RawSampleFormat,
+    pub channels: usize,
+    pub sample_rate: usize,
+}
+
+/// Decode headerless PCM from a file, or stdin when `input` is `-`
+pub fn decode_raw(input: &Path, format: &RawFormat) -> Result<AudioData> {
+    if format.channels == 0 || format.sample_rate == 0 {
+        return Err(Error::Usage("raw input needs a non-zero channel count and sample rate".to_string()));
+    }
+
+    let input_error = |source| Error::Input { path: input.to_path_buf(), source };
+    let bytes_per_sample = format.sample_format.bytes_per_sample();
+    let frame_bytes = bytes_per_sample * format.channels;
+    let (mut reader, total_frames): (Box<dyn Read>, _) = if is_stdio(input) {
+        (Box::new(io::stdin().lock()), None)
+    } else {
+        let file = std::fs::File::open(input).map_err(input_error)?;
+        let frames = file.metadata().ok().map(|m| m.len() / frame_bytes as u64);
+        (Box::new(BufReader::new(file)), frames)
+    };
+
+    // Read a whole number of frames at a time, so only the last chunk can end mid-frame
+    let chunk_bytes = PROGRESS_CHUNK_FRAMES * frame_bytes;
+    let mut chunk = Vec::with_capacity(chunk_bytes);
+    let mut samples = Vec::with_capacity(total_frames.unwrap_or(0) as usize * format.channels);
+    let mut progress = DecodeProgress::new(input, total_frames);
+    let leftover = loop {
+        interrupt::check()?;
+        chunk.clear();
+        reader.by_ref().take(chunk_bytes as u64).read_to_end(&mut chunk).map_err(input_error)?;
+        let whole = chunk.len() - chunk.len() % frame_bytes;
+        samples.extend(chunk[..whole].chunks_exact(bytes_per_sample).map(|b| format.sample_format.decode_sample(b)));
+        progress.advance((whole / frame_bytes) as u64);
+        if chunk.len() < chunk_bytes {
+            break chunk.len() - whole;
+        }
+    };
+    progress.finish();
+    if leftover > 0 {
+        warn!("{}: ignoring {} trailing byte(s) that do not form a whole frame", input.display(), leftover);
+    }
+
+    Ok(AudioData {
+        samples,
+        channels: format.channels,
+        sample_rate: format.sample_rate,
+        bits_per_sample: format.sample_format.bits_per_sample(),
+        is_float: format.sample_format.is_float(),
+        damaged_regions: Vec::new(),
+    })
+}
+
+/// Write audio as headerless PCM to a file, or stdout when `output` is `-`
+pub fn encode_raw(output: &Path, audio_data: &AudioData, sample_format: RawSampleFormat) -> Result<()> {
+    let write = |writer: &mut dyn Write| -> io::Result<()> {
+        let mut out = BufWriter::new(writer);
+        let mut buf = Vec::with_capacity(4096 * sample_format.bytes_per_sample());
+        for chunk in audio_data.samples.chunks(4096) {
+            buf.clear();
+            for v in chunk {
+                sample_format.encode_sample(*v, &mut buf);
+            }
+            out.write_all(&buf)?;
+        }
+        out.flush()
+    };
+
+    let output_error = |e| Error::output(output, e);
+    if is_stdio(output) {
+        return write(&mut io::stdout().lock()).map_err(output_error);
+    }
+    write_atomically(output, |temp| {
+        std::fs::File::create(temp).and_then(|mut file| write(&mut file)).map_err(output_error)
+    })
+}
+
+// G.711 companding, after the reference implementation in Sun's g711.c
+
+fn alaw_to_linear(a: u8) -> i16 {
+    let a = a ^ 0x55;
+    let mut t = ((a & 0x0F) as i16) << 4;
+    let seg = (a & 0x70) >> 4;
+    match seg {
+        0 => t += 8,
+        1 => t += 0x108,
+        _ => t = (t + 0x108) << (seg - 1),
+    }
+    if a & 0x80 != 0 { t } else { -t }
+}
+
+fn mulaw_to_linear(u: u8) -> i16 {
+    let u = !u;
+    let t = ((((u & 0x0F) as i16) << 3) + 0x84) << ((u & 0x70) >> 4);
+    if u & 0x80 != 0 { 0x84 - t } else { t - 0x84 }
+}
+
+/// Index of the first segment end at or above `value`, or 8 when beyond the last
+fn segment(value: i32, ends: &[i32; 8]) -> u8 {
+    ends.iter().position(|&end| value <= end).unwrap_or(8) as u8
+}
+
+fn linear_to_alaw(pcm: i16) -> u8 {
+    const ENDS: [i32; 8] = [0x1F, 0x3F, 0x7F, 0xFF, 0x1FF, 0x3FF, 0x7FF, 0xFFF];
+    let mut value = pcm as i32 >> 3;
+    let mask = if value >= 0 {
+        0xD5
+    } else {
+        value = -value - 1;
+        0x55
+    };
+
+    let seg = segment(value, &ENDS);
+    if seg >= 8 {
+        return 0x7F ^ mask;
+    }
+    let shift = if seg < 2 { 1 } else { seg };
+    ((seg << 4) | ((value >> shift) & 0x0F) as u8) ^ mask
+}
+
+fn linear_to_mulaw(pcm: i16) -> u8 {
+    const ENDS: [i32; 8] = [0x3F, 0x7F, 0xFF, 0x1FF, 0x3FF, 0x7FF, 0xFFF, 0x1FFF];
+    const BIAS: i32 = 0x84;
+    const CLIP: i32 = 8159;
+    let mut value = pcm as i32 >> 2;
+    let mask = if value < 0 {
+        value = -value;
+        0x7F
+    } else {
+        0xFF
+    };
+    value = value.min(CLIP) + (BIAS >> 2);
+
+    let seg = segment(value, &ENDS);
+    if seg >= 8 {
+        return 0x7F ^ mask;
+    }
+    ((seg << 4) | ((value >> (seg + 1)) & 0x0F) as u8) ^ mask
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::temp_path;
+
+    const FORMATS: [RawSampleFormat; 8] = [
+        RawSampleFormat::S8,
+        RawSampleFormat::U8,
+        RawSampleFormat::S16le,
+        RawSampleFormat::S16be,
+        RawSampleFormat::S24le,
+        RawSampleFormat::S32le,
+        RawSampleFormat::F32le,
+        RawSampleFormat::F64le,
+    ];
+
+    #[test]
+    fn alaw_decodes_like_g711_c() {
+        let expected = [
+            (0x00, -5504), (0x01, -5248), (0x08, -7552), (0x10, -2752), (0x20, -22016),
+            (0x2A, -32256), (0x55, -8), (0x80, 5504), (0xAA, 32256), (0xD5, 8),
+        ];
+        for (a, linear) in expected {
+            assert_eq!(alaw_to_linear(a), linear, "A-law {:#04x}", a);
+        }
+    }
+
+    #[test]
+    fn mulaw_decodes_like_g711_c() {
+        let expected = [
+            (0x00, -32124), (0x01, -31100), (0x0F, -16764), (0x10, -15996),
+            (0x7E, -8), (0x7F, 0), (0x80, 32124), (0xFE, 8), (0xFF, 0),
+        ];
+        for (u, linear) in expected {
+            assert_eq!(mulaw_to_linear(u), linear, "µ-law {:#04x}", u);
+        }
+    }
+
+    #[test]
+    fn g711_encodes_like_g711_c() {
+        assert_eq!(linear_to_alaw(0), 0xD5);
+        assert_eq!(linear_to_alaw(-1), 0x55);
+        assert_eq!(linear_to_alaw(i16::MAX), 0xAA);
+        assert_eq!(linear_to_alaw(i16::MIN), 0x2A);
+        assert_eq!(linear_to_mulaw(0), 0xFF);
+        assert_eq!(linear_to_mulaw(-1), 0x7E);
+        assert_eq!(linear_to_mulaw(i16::MAX), 0x80);
+        assert_eq!(linear_to_mulaw(i16::MIN), 0x00);
+    }
+
+    #[test]
+    fn every_g711_code_survives_decode_and_encode() {
+        for code in 0..=255u8 {
+            assert_eq!(linear_to_alaw(alaw_to_linear(code)), code, "A-law {:#04x}", code);
+            // 0x7F is µ-law's negative zero, which encodes back as positive zero
+            let mulaw = if code == 0x7F { 0xFF } else { code };
+            assert_eq!(linear_to_mulaw(mulaw_to_linear(code)), mulaw, "µ-law {:#04x}", code);
+        }
+    }
+
+    #[test]
+    fn sample_formats_round_trip() {
+        // Multiples of 1/128 are exact in every format down to 8 bits
+        let samples: Vec<f32> = (-128..128).map(|i| i as f32 / 128.0).collect();
+        for format in FORMATS {
+            let mut bytes = Vec::new();
+            for &v in &samples {
+                format.encode_sample(v, &mut bytes);
+            }
+            assert_eq!(bytes.len(), samples.len() * format.bytes_per_sample(), "{:?}", format);
+            let decoded: Vec<f32> = bytes.chunks_exact(format.bytes_per_sample()).map(|b| format.decode_sample(b)).collect();
+            assert_eq!(decoded, samples, "{:?}", format);
+        }
+    }
+
+    #[test]
+    fn integer_formats_saturate_at_full_scale() {
+        let mut bytes = Vec::new();
+        RawSampleFormat::S16le.encode_sample(1.5, &mut bytes);
+        RawSampleFormat::S16be.encode_sample(-1.5, &mut bytes);
+        RawSampleFormat::U8.encode_sample(1.0, &mut bytes);
+        assert_eq!(bytes, [0xFF, 0x7F, 0x80, 0x00, 0xFF]);
+    }
+
+    #[test]
+    fn decode_raw_ignores_a_partial_trailing_frame() {
+        let path = temp_path("partial.raw");
+        // Two stereo s16le frames and one stray byte
+        std::fs::write(&path, [0x00, 0x40, 0x00, 0xC0, 0x00, 0x20, 0x00, 0xE0, 0x7F]).unwrap();
+        let format = RawFormat { sample_format: RawSampleFormat::S16le, channels: 2, sample_rate: 8000 };
+        let audio = decode_raw(&path, &format).unwrap();
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(audio.samples, vec![0.5, -0.5, 0.25, -0.25]);
+        assert_eq!((audio.channels, audio.sample_rate, audio.bits_per_sample), (2, 8000, 16));
+    }
+
+    #[test]
+    fn decode_raw_reports_progress_per_chunk() {
+        let path = temp_path("progress.raw");
+        let frames = PROGRESS_CHUNK_FRAMES * 5 / 2;
+        std::fs::write(&path, vec![0u8; frames * 2]).unwrap();
+        let format = RawFormat { sample_format: RawSampleFormat::S16le, channels: 1, sample_rate: 8000 };
+        let (audio, events) = crate::progress::capture_progress(|| decode_raw(&path, &format));
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(audio.unwrap().samples.len(), frames);
+
+        let decoded: Vec<u64> = events.iter().map(|e| e.frames).collect();
+        assert!(decoded.windows(2).all(|w| w[0] <= w[1]), "{:?}", decoded);
+        let chunk = PROGRESS_CHUNK_FRAMES as u64;
+        assert_eq!(&decoded[..4], [0, chunk, 2 * chunk, frames as u64]);
+        let last = events.last().unwrap();
+        assert_eq!((last.frames, last.fraction()), (frames as u64, Some(1.0)));
+    }
+
+    #[test]
+    fn decode_raw_reads_across_chunks() {
+        let path = temp_path("chunks.raw");
+        // Three channels of 24-bit samples, two and a half chunks and a stray byte
+        let frames = PROGRESS_CHUNK_FRAMES * 5 / 2;
+        let expected: Vec<f32> = (0..frames * 3).map(|i| ((i % 2001) as f32 - 1000.0) / 1024.0).collect();
+        let mut bytes = Vec::new();
+        for &v in &expected {
+            RawSampleFormat::S24le.encode_sample(v, &mut bytes);
+        }
+        bytes.push(0x7F);
+        std::fs::write(&path, &bytes).unwrap();
+        let format = RawFormat { sample_format: RawSampleFormat::S24le, channels: 3, sample_rate: 48000 };
+        let audio = decode_raw(&path, &format).unwrap();
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(audio.samples, expected);
+    }
+}
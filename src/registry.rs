@@ -0,0 +1,468 @@
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::sync::OnceLock;
+use crate::error::{Error, Result};
+use crate::multi_format_processor::{is_stdio, AudioData, ContainerFormat, DecodeOptions, MultiFormatProcessor};
+use crate::raw::decode_raw;
+use tracing::debug;
+
+/// Number of header bytes read for `AudioDecoder::sniff`
+pub const SNIFF_LEN: usize = 12;
+
+/// Reads a file format into decoded audio
+pub trait AudioDecoder: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// Lower-case file extensions handled by this decoder
+    fn extensions(&self) -> &'static [&'static str];
+
+    /// Whether the first `SNIFF_LEN` bytes of a file identify this format
+    fn sniff(&self, header: &[u8]) -> bool;
+
+    /// Decode into one segment per run of constant sample rate and channel count
+    fn decode(&self, input: &Path, options: &DecodeOptions) -> Result<Vec<AudioData>>;
+
+    /// Decode from a stream that cannot seek, such as stdin
+    fn decode_stream(&self, input: &Path, _stream: Box<dyn Read + Send + Sync>, _options: &DecodeOptions) -> Result<Vec<AudioData>> {
+        Err(Error::unsupported(input, format!("{} input cannot be read from a stream", self.name())))
+    }
+}
+
+/// Writes decoded audio to a file format
+pub trait AudioEncoder: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// Lower-case file extensions handled by this encoder
+    fn extensions(&self) -> &'static [&'static str];
+
+    /// Short note shown by `--list-formats`
+    fn description(&self) -> &'static str;
+
+    /// Whether the format is actually written, rather than WAV in its place; `--list-formats` lists the others apart
+    fn supported(&self) -> bool {
+        true
+    }
+
+    fn encode(&self, output: &Path, audio_data: &AudioData, bit_depth: u16) -> Result<()>;
+
+    /// Encode to a stream that cannot seek, such as stdout
+    fn encode_stream(&self, output: &Path, _writer: &mut dyn Write, _audio_data: &AudioData, _bit_depth: u16) -> Result<()> {
+        Err(Error::output(output, format!("{} output cannot be written to a stream", self.name())))
+    }
+}
+
+/// Whether a format name given on the command line (`--input-format`, `--output-format`)
+/// names this format or one of its extensions
+fn names_format(name: &str, extensions: &[&str], format: &str) -> bool {
+    let format = format.trim_start_matches('.').to_lowercase();
+    name == format || extensions.contains(&format.as_str())
+}
+
+/// Decoders and encoders looked up by file extension and magic bytes.
+///
+/// Lookups try formats in registration order; the first registered encoder is the
+/// default for outputs with an unknown extension.
+#[derive(Default)]
+pub struct FormatRegistry {
+    decoders: Vec<Box<dyn AudioDecoder>>,
+    encoders: Vec<Box<dyn AudioEncoder>>,
+}
+
+impl FormatRegistry {
+    /// A registry with every built-in format
+    pub fn with_builtin_formats() -> Self {
+        let mut registry = FormatRegistry::default();
+        registry.register_decoder(WavDecoder);
+        registry.register_decoder(SymphoniaDecoder);
+        registry.register_encoder(WavEncoder);
+        registry.register_encoder(Mp3Encoder);
+        registry.register_encoder(FlacEncoder);
+        registry
+    }
+
+    /// The shared registry of built-in formats used by `MultiFormatProcessor`
+    pub fn global() -> &'static FormatRegistry {
+        static REGISTRY: OnceLock<FormatRegistry> = OnceLock::new();
+        REGISTRY.get_or_init(FormatRegistry::with_builtin_formats)
+    }
+
+    pub fn register_decoder(&mut self, decoder: impl AudioDecoder + 'static) {
+        self.decoders.push(Box::new(decoder));
+    }
+
+    pub fn register_encoder(&mut self, encoder: impl AudioEncoder + 'static) {
+        self.encoders.push(Box::new(encoder));
+    }
+
+    pub fn decoders(&self) -> impl Iterator<Item = &dyn AudioDecoder> {
+        self.decoders.iter().map(|d| d.as_ref())
+    }
+
+    pub fn encoders(&self) -> impl Iterator<Item = &dyn AudioEncoder> {
+        self.encoders.iter().map(|e| e.as_ref())
+    }
+
+    /// Find the decoder for a file by its header bytes, then by the `format` name, then by its extension.
+    ///
+    /// Content comes first because extensions lie: some recorders write MP3 data to `.wav` files.
+    pub fn decoder_for(&self, input: &Path, format: Option<&str>) -> Result<&dyn AudioDecoder> {
+        self.find_decoder(input, &read_header(input)?, format)
+    }
+
+    fn find_decoder(&self, input: &Path, header: &[u8], format: Option<&str>) -> Result<&dyn AudioDecoder> {
+        if let Some(decoder) = self.decoders().find(|d| d.sniff(header)) {
+            return Ok(decoder);
+        }
+        if let Some(format) = format {
+            return self
+                .decoders()
+                .find(|d| names_format(d.name(), d.extensions(), format))
+                .ok_or_else(|| Error::Usage(format!("unknown input format '{}' (see --list-formats)", format)));
+        }
+
+        extension(input)
+            .and_then(|ext| self.decoders().find(|d| d.extensions().contains(&ext.as_str())))
+            .ok_or_else(|| Error::unsupported(input, "no decoder recognizes this file"))
+    }
+
+    /// Find the encoder for the `format` name or the output extension, falling back to the default encoder
+    pub fn encoder_for(&self, output: &Path, format: Option<&str>) -> Result<&dyn AudioEncoder> {
+        if let Some(format) = format {
+            return self
+                .encoders()
+                .find(|e| names_format(e.name(), e.extensions(), format))
+                .ok_or_else(|| Error::Usage(format!("unknown output format '{}' (see --list-formats)", format)));
+        }
+
+        let by_extension = extension(output)
+            .and_then(|ext| self.encoders().find(|e| e.extensions().contains(&ext.as_str())));
+        by_extension
+            .or_else(|| self.encoders().next())
+            .ok_or_else(|| Error::output(output, "no encoder registered"))
+    }
+
+    /// Decode a file, or stdin when `input` is `-`
+    pub fn decode(&self, input: &Path, options: &DecodeOptions) -> Result<Vec<AudioData>> {
+        // Headerless PCM cannot be sniffed; its layout comes from the options
+        if let Some(raw) = &options.raw {
+            return Ok(vec![decode_raw(input, raw)?]);
+        }
+
+        let format = options.format.as_deref();
+        if !is_stdio(input) {
+            return self.decoder_for(input, format)?.decode(input, options);
+        }
+
+        let mut stdin = std::io::stdin();
+        let header = read_prefix(&mut stdin, SNIFF_LEN).map_err(|source| Error::Input { path: input.to_path_buf(), source })?;
+        let decoder = self.find_decoder(input, &header, format)?;
+        // Replay the sniffed bytes ahead of the rest of the stream
+        decoder.decode_stream(input, Box::new(std::io::Cursor::new(header).chain(stdin)), options)
+    }
+
+    /// Encode to a file, or to stdout when `output` is `-`
+    pub fn encode(&self, output: &Path, audio_data: &AudioData, bit_depth: u16, format: Option<&str>) -> Result<()> {
+        let encoder = self.encoder_for(output, format)?;
+        if !is_stdio(output) {
+            return encoder.encode(output, audio_data, bit_depth);
+        }
+
+        let mut stdout = std::io::stdout().lock();
+        encoder.encode_stream(output, &mut stdout, audio_data, bit_depth)
+    }
+}
+
+fn extension(path: &Path) -> Option<String> {
+    path.extension().map(|e| e.to_string_lossy().to_lowercase())
+}
+
+/// Read up to `SNIFF_LEN` bytes from the start of a file
+pub fn read_header(input: &Path) -> Result<Vec<u8>> {
+    let input_error = |source| Error::Input { path: input.to_path_buf(), source };
+    let mut file = std::fs::File::open(input).map_err(input_error)?;
+    read_prefix(&mut file, SNIFF_LEN).map_err(input_error)
+}
+
+/// Read up to `len` bytes, stopping early only at the end of the stream
+fn read_prefix(reader: &mut impl Read, len: usize) -> std::io::Result<Vec<u8>> {
+    let mut prefix = vec![0u8; len];
+    let mut filled = 0;
+    while filled < len {
+        match reader.read(&mut prefix[filled..])? {
+            0 => break,
+            n => filled += n,
+        }
+    }
+    prefix.truncate(filled);
+    Ok(prefix)
+}
+
+/// PCM WAV through hound (faster than Symphonia).
+///
+/// RIFF variants hound cannot read correctly, such as ADPCM, A-law, µ-law or
+/// extensible PCM with padded samples, are handed to Symphonia instead.
+pub struct WavDecoder;
+
+impl AudioDecoder for WavDecoder {
+    fn name(&self) -> &'static str {
+        "wav"
+    }
+
+    fn extensions(&self) -> &'static [&'static str] {
+        &["wav", "wave"]
+    }
+
+    fn sniff(&self, header: &[u8]) -> bool {
+        ContainerFormat::sniff(header) == Some(ContainerFormat::Wav)
+    }
+
+    fn decode(&self, input: &Path, options: &DecodeOptions) -> Result<Vec<AudioData>> {
+        if !hound_can_read(input)? {
+            debug!("{}: WAV variant not supported by hound, decoding with Symphonia", input.display());
+            return MultiFormatProcessor::decode_with_symphonia(input, options);
+        }
+
+        match MultiFormatProcessor::decode_wav_with_hound(input, options) {
+            Err(Error::Unsupported { reason, .. }) => {
+                debug!("{}: hound failed ({}), decoding with Symphonia", input.display(), reason);
+                MultiFormatProcessor::decode_with_symphonia(input, options)
+            }
+            result => Ok(vec![result?]),
+        }
+    }
+
+    fn decode_stream(&self, input: &Path, stream: Box<dyn Read + Send + Sync>, options: &DecodeOptions) -> Result<Vec<AudioData>> {
+        // Symphonia reads WAV sequentially and tolerates the unknown sizes piped encoders write
+        MultiFormatProcessor::decode_stream_with_symphonia(input, stream, options)
+    }
+}
+
+/// Whether hound decodes this WAV correctly: integer PCM or IEEE float, either plain or
+/// `WAVE_FORMAT_EXTENSIBLE` with every bit of the sample container valid
+pub(crate) fn hound_can_read(input: &Path) -> Result<bool> {
+    const PCM: u16 = 1;
+    const IEEE_FLOAT: u16 = 3;
+    const EXTENSIBLE: u16 = 0xFFFE;
+
+    let input_error = |source| Error::Input { path: input.to_path_buf(), source };
+    let mut file = std::io::BufReader::new(std::fs::File::open(input).map_err(input_error)?);
+    let mut riff = [0u8; 12];
+    if file.read_exact(&mut riff).is_err() || ContainerFormat::sniff(&riff) != Some(ContainerFormat::Wav) {
+        return Ok(false);
+    }
+
+    // Walk the chunks up to `fmt `
+    let mut chunk = [0u8; 8];
+    while file.read_exact(&mut chunk).is_ok() {
+        let size = u32::from_le_bytes([chunk[4], chunk[5], chunk[6], chunk[7]]);
+        if &chunk[..4] != b"fmt " {
+            file.seek(SeekFrom::Current(size as i64 + (size & 1) as i64)).map_err(input_error)?;
+            continue;
+        }
+
+        // Only the first 26 bytes matter; never trust the declared size for an allocation
+        let mut fmt = vec![0u8; size.min(40) as usize];
+        if file.read_exact(&mut fmt).is_err() || fmt.len() < 16 {
+            return Ok(false);
+        }
+        let u16_at = |offset: usize| u16::from_le_bytes([fmt[offset], fmt[offset + 1]]);
+        let (tag, bits) = (u16_at(0), u16_at(14));
+        return Ok(match tag {
+            PCM | IEEE_FLOAT => true,
+            EXTENSIBLE if fmt.len() >= 26 => {
+                let (valid_bits, sub_format) = (u16_at(18), u16_at(24));
+                valid_bits == bits && (sub_format == PCM || sub_format == IEEE_FLOAT)
+            }
+            _ => false,
+        });
+    }
+    Ok(false)
+}
+
+/// Every container and codec Symphonia supports (MP3, AAC, FLAC, Vorbis, ALAC, AIFF, CAF, ...)
+pub struct SymphoniaDecoder;
+
+impl AudioDecoder for SymphoniaDecoder {
+    fn name(&self) -> &'static str {
+        "symphonia"
+    }
+
+    fn extensions(&self) -> &'static [&'static str] {
+        &["mp3", "mp2", "mp1", "flac", "ogg", "oga", "m4a", "m4b", "mp4", "aac", "mkv", "mka", "webm", "aif", "aiff", "aifc", "caf"]
+    }
+
+    fn sniff(&self, header: &[u8]) -> bool {
+        ContainerFormat::sniff(header).is_some()
+    }
+
+    fn decode(&self, input: &Path, options: &DecodeOptions) -> Result<Vec<AudioData>> {
+        MultiFormatProcessor::decode_with_symphonia(input, options)
+    }
+
+    fn decode_stream(&self, input: &Path, stream: Box<dyn Read + Send + Sync>, options: &DecodeOptions) -> Result<Vec<AudioData>> {
+        MultiFormatProcessor::decode_stream_with_symphonia(input, stream, options)
+    }
+}
+
+/// PCM WAV through hound, at the source bit depth
+pub struct WavEncoder;
+
+impl AudioEncoder for WavEncoder {
+    fn name(&self) -> &'static str {
+        "wav"
+    }
+
+    fn extensions(&self) -> &'static [&'static str] {
+        &["wav", "wave"]
+    }
+
+    fn description(&self) -> &'static str {
+        "PCM WAV (8/16/24/32-bit integer, 32-bit float)"
+    }
+
+    fn encode(&self, output: &Path, audio_data: &AudioData, bit_depth: u16) -> Result<()> {
+        MultiFormatProcessor::write_wav(output, audio_data, bit_depth)
+    }
+
+    fn encode_stream(&self, output: &Path, writer: &mut dyn Write, audio_data: &AudioData, bit_depth: u16) -> Result<()> {
+        MultiFormatProcessor::write_wav_stream(output, writer, audio_data, bit_depth)
+    }
+}
+
+/// Placeholder until MP3 encoding is implemented: writes 16-bit WAV under a `.wav` extension
+pub struct Mp3Encoder;
+
+impl AudioEncoder for Mp3Encoder {
+    fn name(&self) -> &'static str {
+        "mp3"
+    }
+
+    fn extensions(&self) -> &'static [&'static str] {
+        &["mp3"]
+    }
+
+    fn description(&self) -> &'static str {
+        "not yet implemented; written as WAV"
+    }
+
+    fn supported(&self) -> bool {
+        false
+    }
+
+    fn encode(&self, output: &Path, audio_data: &AudioData, _bit_depth: u16) -> Result<()> {
+        MultiFormatProcessor::write_mp3(output, audio_data)
+    }
+}
+
+/// Placeholder until FLAC encoding is implemented: writes WAV of 24 bits or more under a `.wav` extension
+pub struct FlacEncoder;
+
+impl AudioEncoder for FlacEncoder {
+    fn name(&self) -> &'static str {
+        "flac"
+    }
+
+    fn extensions(&self) -> &'static [&'static str] {
+        &["flac"]
+    }
+
+    fn description(&self) -> &'static str {
+        "not yet implemented; written as WAV"
+    }
+
+    fn supported(&self) -> bool {
+        false
+    }
+
+    fn encode(&self, output: &Path, audio_data: &AudioData, bit_depth: u16) -> Result<()> {
+        MultiFormatProcessor::write_flac(output, audio_data, bit_depth)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::temp_path;
+    use std::path::PathBuf;
+
+    /// A WAV header with the given `fmt ` chunk, followed by a short data chunk
+    fn wav_with_fmt(name: &str, fmt_size: u32, fmt: &[u8]) -> PathBuf {
+        let path = temp_path(name);
+        let mut bytes = b"RIFF\0\0\0\0WAVE".to_vec();
+        bytes.extend_from_slice(b"LIST\x04\0\0\0INFO");
+        bytes.extend_from_slice(b"fmt ");
+        bytes.extend_from_slice(&fmt_size.to_le_bytes());
+        bytes.extend_from_slice(fmt);
+        bytes.extend_from_slice(b"data\x04\0\0\0\0\0\0\0");
+        std::fs::write(&path, bytes).unwrap();
+        path
+    }
+
+    /// `fmt ` body: tag, 2 channels, 48 kHz, `bits` per sample, and the extensible fields when given
+    fn fmt(tag: u16, bits: u16, extensible: Option<(u16, u16)>) -> Vec<u8> {
+        let block_align = 2 * bits.div_ceil(8);
+        let mut fmt = Vec::new();
+        fmt.extend_from_slice(&tag.to_le_bytes());
+        fmt.extend_from_slice(&2u16.to_le_bytes());
+        fmt.extend_from_slice(&48000u32.to_le_bytes());
+        fmt.extend_from_slice(&(48000 * block_align as u32).to_le_bytes());
+        fmt.extend_from_slice(&block_align.to_le_bytes());
+        fmt.extend_from_slice(&bits.to_le_bytes());
+        if let Some((valid_bits, sub_format)) = extensible {
+            fmt.extend_from_slice(&22u16.to_le_bytes());
+            fmt.extend_from_slice(&valid_bits.to_le_bytes());
+            fmt.extend_from_slice(&3u32.to_le_bytes());
+            fmt.extend_from_slice(&sub_format.to_le_bytes());
+            fmt.extend_from_slice(&[0x00, 0x00, 0x00, 0x00, 0x10, 0x00, 0x80, 0x00, 0x00, 0xAA, 0x00, 0x38, 0x9B, 0x71]);
+        }
+        fmt
+    }
+
+    fn can_read(name: &str, fmt_size: Option<u32>, fmt: Vec<u8>) -> bool {
+        let path = wav_with_fmt(name, fmt_size.unwrap_or(fmt.len() as u32), &fmt);
+        let result = hound_can_read(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+        result
+    }
+
+    #[test]
+    fn hound_reads_plain_pcm_and_float() {
+        assert!(can_read("pcm.wav", None, fmt(1, 16, None)));
+        assert!(can_read("float.wav", None, fmt(3, 32, None)));
+    }
+
+    #[test]
+    fn hound_does_not_read_g711() {
+        assert!(!can_read("alaw.wav", None, fmt(6, 8, None)));
+        assert!(!can_read("mulaw.wav", None, fmt(7, 8, None)));
+    }
+
+    #[test]
+    fn hound_reads_extensible_only_with_every_container_bit_valid() {
+        assert!(can_read("ext24.wav", None, fmt(0xFFFE, 24, Some((24, 1)))));
+        assert!(can_read("extfloat.wav", None, fmt(0xFFFE, 32, Some((32, 3)))));
+        assert!(!can_read("ext20.wav", None, fmt(0xFFFE, 24, Some((20, 1)))));
+        assert!(!can_read("extalaw.wav", None, fmt(0xFFFE, 8, Some((8, 6)))));
+    }
+
+    #[test]
+    fn huge_fmt_size_is_not_allocated() {
+        assert!(can_read("huge.wav", Some(u32::MAX - 1), fmt(0xFFFE, 24, Some((24, 1)))));
+        // Fewer bytes left in the file than the capped read needs
+        assert!(!can_read("short.wav", Some(u32::MAX - 1), fmt(1, 16, None)[..8].to_vec()));
+    }
+
+    #[test]
+    fn only_wav_is_supported_for_writing() {
+        let supported: Vec<_> = FormatRegistry::global().encoders().filter(|e| e.supported()).map(|e| e.name()).collect();
+        assert_eq!(supported, ["wav"]);
+    }
+
+    #[test]
+    fn non_wav_is_not_read_by_hound() {
+        let path = temp_path("notwav");
+        std::fs::write(&path, b"fLaC\0\0\0\x22").unwrap();
+        assert!(!hound_can_read(&path).unwrap());
+        let _ = std::fs::remove_file(&path);
+    }
+}
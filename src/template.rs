@@ -0,0 +1,244 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+use crate::multi_format_processor::is_stdio;
+
+/// An output path with `{placeholder}`s filled in per input, e.g. `{dir}/normalized/{stem}.wav`.
+///
+/// Placeholders:
+/// - `{stem}`, `{ext}`, `{name}` - the input file name without extension, its extension, and both
+/// - `{dir}` - the directory containing the input (`.` when it has none)
+/// - `{target}` - the requested target (LUFS, or dBFS for peak normalization)
+/// - `{measured}` - the level measured before processing, to one decimal
+/// - `{date}` - today's local date as `YYYY-MM-DD`
+///
+/// `{{` and `}}` stand for literal braces.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutputTemplate {
+    parts: Vec<Part>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Placeholder {
+    Stem,
+    Ext,
+    Name,
+    Dir,
+    Target,
+    Measured,
+    Date,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Part {
+    Text(String),
+    Placeholder(Placeholder),
+}
+
+/// Values that depend on the processing rather than on the input path
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TemplateValues {
+    /// Requested target level
+    pub target: f64,
+    /// Level measured before processing, for `{measured}`
+    pub measured: Option<f64>,
+}
+
+impl OutputTemplate {
+    /// A template that always renders to `path`, braces included
+    pub fn literal(path: &Path) -> Self {
+        OutputTemplate { parts: vec![Part::Text(path.to_string_lossy().into_owned())] }
+    }
+
+    /// Whether the rendered path depends on the decoded audio
+    pub fn needs_measurement(&self) -> bool {
+        self.parts.contains(&Part::Placeholder(Placeholder::Measured))
+    }
+
+    /// Whether the template contains any placeholder
+    pub fn has_placeholders(&self) -> bool {
+        self.parts.iter().any(|p| matches!(p, Part::Placeholder(_)))
+    }
+
+    /// Fill in the placeholders for `input`. `{measured}` renders as `unknown` when no measurement is given.
+    pub fn render(&self, input: &Path, values: &TemplateValues) -> PathBuf {
+        let (stem, ext, name, dir) = if is_stdio(input) {
+            ("stdin".to_string(), String::new(), "stdin".to_string(), ".".to_string())
+        } else {
+            let lossy = |s: Option<&std::ffi::OsStr>| s.map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+            let dir = match input.parent() {
+                Some(parent) if !parent.as_os_str().is_empty() => parent.to_string_lossy().into_owned(),
+                _ => ".".to_string(),
+            };
+            (lossy(input.file_stem()), lossy(input.extension()), lossy(input.file_name()), dir)
+        };
+
+        let mut rendered = String::new();
+        for part in &self.parts {
+            match part {
+                Part::Text(text) => rendered.push_str(text),
+                Part::Placeholder(Placeholder::Stem) => rendered.push_str(&stem),
+                Part::Placeholder(Placeholder::Ext) => rendered.push_str(&ext),
+                Part::Placeholder(Placeholder::Name) => rendered.push_str(&name),
+                Part::Placeholder(Placeholder::Dir) => rendered.push_str(&dir),
+                Part::Placeholder(Placeholder::Target) => rendered.push_str(&values.target.to_string()),
+                Part::Placeholder(Placeholder::Measured) => match values.measured {
+                    Some(measured) if measured.is_finite() => rendered.push_str(&format!("{:.1}", measured)),
+                    _ => rendered.push_str("unknown"),
+                },
+                Part::Placeholder(Placeholder::Date) => {
+                    rendered.push_str(&chrono::Local::now().format("%Y-%m-%d").to_string())
+                }
+            }
+        }
+        PathBuf::from(rendered)
+    }
+}
+
+impl std::str::FromStr for OutputTemplate {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = Vec::new();
+        let mut text = String::new();
+        let mut chars = s.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    text.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    text.push('}');
+                }
+                '{' => {
+                    let mut name = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => name.push(c),
+                            None => return Err(format!("unclosed placeholder '{{{}'", name)),
+                        }
+                    }
+                    let placeholder = match name.as_str() {
+                        "stem" => Placeholder::Stem,
+                        "ext" => Placeholder::Ext,
+                        "name" => Placeholder::Name,
+                        "dir" => Placeholder::Dir,
+                        "target" => Placeholder::Target,
+                        "measured" => Placeholder::Measured,
+                        "date" => Placeholder::Date,
+                        other => {
+                            return Err(format!(
+                                "unknown placeholder '{{{}}}' (expected stem, ext, name, dir, target, measured or date)",
+                                other
+                            ))
+                        }
+                    };
+                    if !text.is_empty() {
+                        parts.push(Part::Text(std::mem::take(&mut text)));
+                    }
+                    parts.push(Part::Placeholder(placeholder));
+                }
+                '}' => return Err("unmatched '}' (write '}}' for a literal brace)".to_string()),
+                c => text.push(c),
+            }
+        }
+        if !text.is_empty() {
+            parts.push(Part::Text(text));
+        }
+        if parts.is_empty() {
+            return Err("the output template is empty".to_string());
+        }
+        Ok(OutputTemplate { parts })
+    }
+}
+
+impl fmt::Display for OutputTemplate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for part in &self.parts {
+            match part {
+                Part::Text(text) => write!(f, "{}", text.replace('{', "{{").replace('}', "}}"))?,
+                Part::Placeholder(p) => {
+                    let name = match p {
+                        Placeholder::Stem => "stem",
+                        Placeholder::Ext => "ext",
+                        Placeholder::Name => "name",
+                        Placeholder::Dir => "dir",
+                        Placeholder::Target => "target",
+                        Placeholder::Measured => "measured",
+                        Placeholder::Date => "date",
+                    };
+                    write!(f, "{{{}}}", name)?
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VALUES: TemplateValues = TemplateValues { target: -16.0, measured: Some(-18.349) };
+
+    fn render(template: &str, input: &str) -> PathBuf {
+        template.parse::<OutputTemplate>().unwrap().render(Path::new(input), &VALUES)
+    }
+
+    #[test]
+    fn renders_path_placeholders() {
+        assert_eq!(render("{dir}/normalized/{stem}.wav", "music/ep01.flac"), Path::new("music/normalized/ep01.wav"));
+        assert_eq!(render("{dir}/{name}.bak", "ep01.flac"), Path::new("./ep01.flac.bak"));
+        assert_eq!(render("out/{stem}.{ext}", "a.b.mp3"), Path::new("out/a.b.mp3"));
+        assert_eq!(render("{stem}{ext}.wav", "-"), Path::new("stdin.wav"));
+    }
+
+    #[test]
+    fn renders_levels_and_date() {
+        assert_eq!(render("{stem}_{target}_{measured}.wav", "a.wav"), Path::new("a_-16_-18.3.wav"));
+        let unmeasured: OutputTemplate = "{measured}.wav".parse().unwrap();
+        let values = TemplateValues { measured: None, ..VALUES };
+        assert_eq!(unmeasured.render(Path::new("a.wav"), &values), Path::new("unknown.wav"));
+        let silent = TemplateValues { measured: Some(f64::NEG_INFINITY), ..VALUES };
+        assert_eq!(unmeasured.render(Path::new("a.wav"), &silent), Path::new("unknown.wav"));
+        let date = render("{date}", "a.wav").to_string_lossy().into_owned();
+        assert!(chrono::NaiveDate::parse_from_str(&date, "%Y-%m-%d").is_ok(), "{}", date);
+    }
+
+    #[test]
+    fn doubled_braces_are_literal() {
+        assert_eq!(render("{{{stem}}}.wav", "a.wav"), Path::new("{a}.wav"));
+        let template: OutputTemplate = "{{x}}/{stem}.wav".parse().unwrap();
+        assert!(template.has_placeholders());
+        assert_eq!(template.to_string().parse::<OutputTemplate>().unwrap(), template);
+    }
+
+    #[test]
+    fn literal_paths_keep_their_braces() {
+        let literal = OutputTemplate::literal(Path::new("odd{name}.wav"));
+        assert!(!literal.has_placeholders());
+        assert_eq!(literal.render(Path::new("a.wav"), &VALUES), Path::new("odd{name}.wav"));
+        assert_eq!(literal.to_string().parse::<OutputTemplate>().unwrap().render(Path::new("a.wav"), &VALUES), Path::new("odd{name}.wav"));
+    }
+
+    #[test]
+    fn reports_whether_measurement_is_needed() {
+        assert!("{stem}_{measured}.wav".parse::<OutputTemplate>().unwrap().needs_measurement());
+        assert!(!"{stem}_{target}.wav".parse::<OutputTemplate>().unwrap().needs_measurement());
+    }
+
+    #[test]
+    fn rejects_malformed_templates() {
+        for (template, error) in [
+            ("{stem", "unclosed placeholder"),
+            ("{size}.wav", "unknown placeholder '{size}'"),
+            ("a}.wav", "unmatched '}'"),
+            ("", "empty"),
+        ] {
+            let e = template.parse::<OutputTemplate>().unwrap_err();
+            assert!(e.contains(error), "{}: {}", template, e);
+        }
+    }
+}
@@ -0,0 +1,16 @@
+//! Helpers shared by the unit tests
+
+use std::path::PathBuf;
+
+/// A scratch path for one test, in the system temporary directory and unique to this process
+pub(crate) fn temp_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("audio_normalizer-{}-{}", std::process::id(), name))
+}
+
+/// A fresh, empty scratch directory for one test
+pub(crate) fn temp_dir(name: &str) -> PathBuf {
+    let dir = temp_path(name);
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
@@ -0,0 +1,53 @@
+//! Interrupts set a process-wide flag that is never cleared, so they are tested in their own
+//! test binary rather than beside the library's unit tests.
+
+use audio_normalizer::{
+    decode_raw, interrupt, is_interrupted, normalize_peak, Error, NormalizeOptions, ProgressLayer, RawFormat,
+    RawSampleFormat, PROGRESS_TARGET,
+};
+use std::sync::{Arc, Mutex};
+use tracing_subscriber::filter::{LevelFilter, Targets};
+use tracing_subscriber::prelude::*;
+
+#[test]
+fn an_interrupt_stops_decoding_and_writes_nothing() {
+    let dir = std::env::temp_dir().join(format!("audio_normalizer-{}-interrupt", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let input = dir.join("long.raw");
+    let frames = 1 << 20;
+    std::fs::write(&input, vec![0u8; frames * 2]).unwrap();
+    let format = RawFormat { sample_format: RawSampleFormat::S16le, channels: 1, sample_rate: 48000 };
+
+    // Interrupt as soon as the first chunk is reported, as a signal arriving mid-decode would
+    let decoded = Arc::new(Mutex::new(Vec::new()));
+    let sink = Arc::clone(&decoded);
+    let layer = ProgressLayer::new(move |event| {
+        if event.frames > 0 {
+            interrupt();
+        }
+        sink.lock().unwrap().push(event.frames);
+    })
+    .with_filter(Targets::new().with_target(PROGRESS_TARGET, LevelFilter::TRACE));
+    let result = tracing::subscriber::with_default(tracing_subscriber::registry().with(layer), || {
+        decode_raw(&input, &format)
+    });
+    assert!(matches!(result, Err(Error::Interrupted)), "{:?}", result.map(|audio| audio.samples.len()));
+    assert!(is_interrupted());
+    let decoded = decoded.lock().unwrap();
+    assert!(decoded.len() == 2 && decoded[1] < frames as u64, "{:?}", decoded);
+
+    // Once interrupted, nothing more is decoded or written
+    let wav = dir.join("tone.wav");
+    let spec = hound::WavSpec { channels: 1, sample_rate: 8000, bits_per_sample: 16, sample_format: hound::SampleFormat::Int };
+    let mut writer = hound::WavWriter::create(&wav, spec).unwrap();
+    for i in 0..8000 {
+        writer.write_sample(((i % 40) * 200 - 4000) as i16).unwrap();
+    }
+    writer.finalize().unwrap();
+    let output = dir.join("out.wav");
+    let result = normalize_peak(&wav, &output, -6.0, &NormalizeOptions::default());
+    assert!(matches!(result, Err(Error::Interrupted)), "{:?}", result);
+    assert!(!output.exists());
+    let _ = std::fs::remove_dir_all(&dir);
+}